@@ -1,22 +1,31 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph, Tabs},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Tabs, Wrap},
     Terminal,
 };
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     env,
-    io::{self, BufRead, BufReader},
-    process::{Command, Stdio},
-    sync::{Arc, Mutex},
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc, Arc, Condvar, Mutex,
+    },
     thread,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Clone, Copy, PartialEq)]
@@ -30,37 +39,345 @@ enum Tab {
 enum InputMode {
     Normal,
     EditingPath,
+    EditingRequeueDest, // Destination for downloading a history entry again
+    ExportingHistory,   // File to export the shown history entries to
+    MarkingGlob,        // Pattern of folder entries to mark
+    JumpingToPath,      // Remote folder to go to
+    PickingBookmark,    // Filter of the bookmark picker
     Filtering,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
+enum SortMode {
+    Name,
+    Natural,
+    Size,
+    Modified,
+}
+
+impl SortMode {
+    fn next(self) -> Self {
+        match self {
+            SortMode::Name => SortMode::Natural,
+            SortMode::Natural => SortMode::Size,
+            SortMode::Size => SortMode::Modified,
+            SortMode::Modified => SortMode::Name,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortMode::Name => "name",
+            SortMode::Natural => "natural",
+            SortMode::Size => "size",
+            SortMode::Modified => "mtime",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 enum DownloadStatus {
     Queued,
     Downloading,
+    Paused,
+    Completed,
+    Failed(String),
+    Cancelled,
+}
+
+/// How a download ended, as kept in history
+#[derive(Clone, Debug, PartialEq)]
+enum Outcome {
     Completed,
     Failed(String),
+    Cancelled,
+}
+
+/// Which history entries the History tab shows
+#[derive(Clone, Copy, PartialEq)]
+enum OutcomeFilter {
+    All,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl OutcomeFilter {
+    fn next(self) -> Self {
+        match self {
+            OutcomeFilter::All => OutcomeFilter::Completed,
+            OutcomeFilter::Completed => OutcomeFilter::Failed,
+            OutcomeFilter::Failed => OutcomeFilter::Cancelled,
+            OutcomeFilter::Cancelled => OutcomeFilter::All,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            OutcomeFilter::All => "all",
+            OutcomeFilter::Completed => "completed",
+            OutcomeFilter::Failed => "failed",
+            OutcomeFilter::Cancelled => "cancelled",
+        }
+    }
+
+    fn matches(self, outcome: &Outcome) -> bool {
+        matches!(
+            (self, outcome),
+            (OutcomeFilter::All, _)
+                | (OutcomeFilter::Completed, Outcome::Completed)
+                | (OutcomeFilter::Failed, Outcome::Failed(_))
+                | (OutcomeFilter::Cancelled, Outcome::Cancelled)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EntryKind {
+    Directory,
+    File,
+    Symlink { to_dir: bool },
 }
 
 #[derive(Clone)]
 struct FolderInfo {
-    name: String,
+    name: OsString, // Exact bytes from the remote, used for paths
+    display: String, // Lossy UTF-8 form for the UI, filtering and sorting
+    kind: EntryKind,
+    size: Option<u64>, // Recursive size for directories, None when not computed
+    modified: u64,
+    owner: String,
+    mode: u32,
+}
+
+impl FolderInfo {
+    /// Whether the entry can be entered (a directory or a link to one)
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, EntryKind::Directory | EntryKind::Symlink { to_dir: true })
+    }
+
+    /// Name with an `ls -F` style suffix marking directories and symlinks
+    fn display_name(&self) -> String {
+        match self.kind {
+            EntryKind::Directory => format!("{}/", self.display),
+            EntryKind::File => self.display.clone(),
+            EntryKind::Symlink { .. } => format!("{}@", self.display),
+        }
+    }
+
+    /// Permission bits rendered like `ls -l`, e.g. `drwxr-xr-x`
+    fn mode_string(&self) -> String {
+        let mut out = String::with_capacity(10);
+        out.push(match self.kind {
+            EntryKind::Directory => 'd',
+            EntryKind::File => '-',
+            EntryKind::Symlink { .. } => 'l',
+        });
+        for shift in [6, 3, 0] {
+            let bits = (self.mode >> shift) & 0o7;
+            out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            out.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+        }
+        out
+    }
 }
 
 #[derive(Clone)]
 struct Download {
     id: u64,
     folder_name: String,
-    remote_path: String,
+    remote_host: String,
+    remote_path: PathBuf,
+    local_dest: String,
+    kind: EntryKind,
     status: DownloadStatus,
+    progress: Option<DownloadProgress>,
+    child: Option<Arc<Mutex<Child>>>, // Running rsync process, so it can be killed
+    resume: bool,                     // Continue partial files from an earlier run
+    pause_requested: bool,            // rsync was asked to stop; pause once it has exited
+    attempt: u32,                     // Automatic retries used so far
+    retry_at: Option<u64>,            // Earliest time a retry may start
+    stderr_tail: VecDeque<String>,    // Last STDERR_TAIL_LINES lines rsync wrote to stderr
+    total_size: Option<u64>,          // Size of the source as reported by rsync at the end
+    recorded: bool,                   // Outcome already written to history
     started_at: Option<u64>,
     completed_at: Option<u64>,
 }
 
-#[derive(Clone)]
+impl Download {
+    /// A new download waiting for a worker
+    fn queued(
+        id: u64,
+        folder_name: String,
+        remote_host: String,
+        remote_path: PathBuf,
+        local_dest: String,
+        kind: EntryKind,
+    ) -> Self {
+        Download {
+            id,
+            folder_name,
+            remote_host,
+            remote_path,
+            local_dest,
+            kind,
+            status: DownloadStatus::Queued,
+            progress: None,
+            child: None,
+            resume: false,
+            pause_requested: false,
+            attempt: 0,
+            retry_at: None,
+            stderr_tail: VecDeque::new(),
+            total_size: None,
+            recorded: false,
+            started_at: None,
+            completed_at: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 struct HistoryEntry {
     folder_name: String,
-    remote_path: String,
-    downloaded_at: u64,
+    remote_host: String,
+    remote_path: PathBuf, // Exact bytes, so the entry can be downloaded again
+    local_dest: String,
+    kind: EntryKind,
+    outcome: Outcome,
+    bytes: Option<u64>,
+    duration: u64, // Seconds from the first start until it ended
+    downloaded_at: u64, // When it ended, whatever the outcome
+}
+
+impl HistoryEntry {
+    /// One line of the history file: tab separated fields, escaped with
+    /// `escape_field`
+    fn to_line(&self) -> String {
+        [
+            self.downloaded_at.to_string(),
+            self.duration.to_string(),
+            self.bytes.map(|b| b.to_string()).unwrap_or_default(),
+            escape_field(self.remote_host.as_bytes()),
+            escape_field(self.remote_path.as_os_str().as_bytes()),
+            escape_field(self.local_dest.as_bytes()),
+            escape_field(self.folder_name.as_bytes()),
+            match self.kind {
+                EntryKind::Directory => "d",
+                EntryKind::File => "f",
+                EntryKind::Symlink { to_dir: false } => "l",
+                EntryKind::Symlink { to_dir: true } => "L",
+            }
+            .to_string(),
+            match &self.outcome {
+                Outcome::Completed => "completed".to_string(),
+                Outcome::Failed(reason) => format!("failed:{}", escape_field(reason.as_bytes())),
+                Outcome::Cancelled => "cancelled".to_string(),
+            },
+        ]
+        .join("\t")
+    }
+
+    /// Parse a line written by `to_line`, or None if it is malformed
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let downloaded_at = fields.next()?.parse().ok()?;
+        let duration = fields.next()?.parse().ok()?;
+        let bytes = match fields.next()? {
+            "" => None,
+            b => Some(b.parse().ok()?),
+        };
+        let remote_host = String::from_utf8_lossy(&unescape_field(fields.next()?)).into_owned();
+        let remote_path = PathBuf::from(OsString::from_vec(unescape_field(fields.next()?)));
+        let local_dest = String::from_utf8_lossy(&unescape_field(fields.next()?)).into_owned();
+        let folder_name = String::from_utf8_lossy(&unescape_field(fields.next()?)).into_owned();
+        let kind = match fields.next()? {
+            "d" => EntryKind::Directory,
+            "f" => EntryKind::File,
+            "l" => EntryKind::Symlink { to_dir: false },
+            "L" => EntryKind::Symlink { to_dir: true },
+            _ => return None,
+        };
+        let outcome = match fields.next()? {
+            "completed" => Outcome::Completed,
+            "cancelled" => Outcome::Cancelled,
+            field => {
+                let reason = field.strip_prefix("failed:")?;
+                Outcome::Failed(String::from_utf8_lossy(&unescape_field(reason)).into_owned())
+            }
+        };
+        Some(HistoryEntry {
+            folder_name,
+            remote_host,
+            remote_path,
+            local_dest,
+            kind,
+            outcome,
+            bytes,
+            duration,
+            downloaded_at,
+        })
+    }
+}
+
+/// A remote folder saved for going back to later
+#[derive(Clone, Debug, PartialEq)]
+struct Bookmark {
+    host: String,
+    path: PathBuf,
+}
+
+impl Bookmark {
+    /// `host:path` as shown in the picker
+    fn label(&self) -> String {
+        format!("{}:{}", self.host, display_lossy(self.path.as_os_str().as_bytes()))
+    }
+
+    /// One line of the bookmarks file: host and path separated by a tab, escaped
+    /// with `escape_field`
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}",
+            escape_field(self.host.as_bytes()),
+            escape_field(self.path.as_os_str().as_bytes())
+        )
+    }
+
+    /// Parse a line written by `to_line`, or None if it is malformed
+    fn from_line(line: &str) -> Option<Self> {
+        let (host, path) = line.split_once('\t')?;
+        let host = String::from_utf8_lossy(&unescape_field(host)).into_owned();
+        if host.is_empty() {
+            return None;
+        }
+        let path = PathBuf::from(OsString::from_vec(unescape_field(path)));
+        Some(Bookmark { host, path })
+    }
+}
+
+/// rsync exit codes worth retrying: 10 socket I/O, 12 protocol data stream,
+/// 23/24 partial transfer, 30/35 timeouts and 255 from ssh losing the connection
+const RETRYABLE_EXIT_CODES: [i32; 7] = [10, 12, 23, 24, 30, 35, 255];
+
+/// How many stderr lines to keep per download
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Longest wait between automatic retries, in seconds
+const MAX_RETRY_DELAY: u64 = 300;
+
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    max_retries: u32,
+    base_delay: u64, // Seconds before the first retry, doubled for each further one
+}
+
+impl RetryPolicy {
+    /// Seconds to wait before retry number `attempt` (starting at 1)
+    fn delay(&self, attempt: u32) -> u64 {
+        let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+        self.base_delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+    }
 }
 
 #[derive(Clone)]
@@ -68,12 +385,293 @@ struct DownloadProgress {
     file_name: String,
     percentage: u16,
     speed: String,
+    bytes_transferred: u64,
+    eta: String,
+}
+
+/// What to do with a folder listing once it arrives
+#[derive(Clone)]
+enum ListingPurpose {
+    /// A starting folder, possibly on another host: resolve it to an absolute
+    /// path first, then move there
+    Connect { host: String },
+    /// Move to the listed folder, selecting the entry with this name if given
+    Navigate { select: Option<OsString> },
+    /// Fresh contents of the current folder; keep the filter and selection
+    Reload,
+    /// Only fill the cache, to complete the path being typed into the jump prompt
+    Complete,
+    /// Step back (or forward) through the navigation history to the place on
+    /// top of that stack, which is on `host`
+    Revisit { host: String, back: bool },
+}
+
+/// A folder listing running in the background
+struct PendingListing {
+    id: u64,
+    host: String,
+    path: PathBuf,
+    purpose: ListingPurpose,
+    message: String, // Status once it arrives
+    with_sizes: bool,
+    child: Arc<Mutex<Option<Child>>>,
+}
+
+/// How long a folder listing is reused before it is fetched again
+const LISTING_CACHE_TTL: Duration = Duration::from_secs(300);
+
+struct CachedListing {
+    folders: Vec<FolderInfo>,
+    with_sizes: bool,
+    fetched_at: Instant,
+}
+
+/// Recent folder listings per host and folder
+#[derive(Default)]
+struct ListingCache {
+    entries: HashMap<(String, PathBuf), CachedListing>,
+}
+
+impl ListingCache {
+    /// The listing of `key` if it is younger than the TTL and, when
+    /// `with_sizes` is asked for, has folder sizes. Connecting resolves the
+    /// path before listing it, so it never uses the cache.
+    fn lookup(
+        &self,
+        key: &(String, PathBuf),
+        purpose: &ListingPurpose,
+        with_sizes: bool,
+        now: Instant,
+    ) -> Option<&[FolderInfo]> {
+        if matches!(purpose, ListingPurpose::Connect { .. }) {
+            return None;
+        }
+        let cached = self.entries.get(key)?;
+        let fresh = now.saturating_duration_since(cached.fetched_at) < LISTING_CACHE_TTL;
+        (fresh && (cached.with_sizes || !with_sizes)).then_some(cached.folders.as_slice())
+    }
+
+    fn insert(&mut self, key: (String, PathBuf), folders: Vec<FolderInfo>, with_sizes: bool, now: Instant) {
+        // Drop expired entries while adding the new one, so the cache can't grow forever
+        self.entries
+            .retain(|_, cached| now.saturating_duration_since(cached.fetched_at) < LISTING_CACHE_TTL);
+        self.entries.insert(
+            key,
+            CachedListing {
+                folders,
+                with_sizes,
+                fetched_at: now,
+            },
+        );
+    }
+
+    /// Forget the listing of `key`, so the next request fetches it again
+    fn invalidate(&mut self, key: &(String, PathBuf)) {
+        self.entries.remove(key);
+    }
+}
+
+impl Drop for PendingListing {
+    fn drop(&mut self) {
+        // Stop the ssh process when the listing is cancelled or replaced
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            let _ = child.kill();
+        }
+    }
+}
+
+struct ListingResponse {
+    id: u64,
+    home: Option<PathBuf>, // Resolved when connecting
+    path: PathBuf,         // Resolved when connecting
+    result: io::Result<Vec<FolderInfo>>,
+}
+
+/// A folder the browser showed, as it was when the user moved on
+#[derive(Clone, Debug, PartialEq)]
+struct NavEntry {
+    host: String,
+    home: PathBuf,
+    base: PathBuf, // Starting folder on `host`, restored when coming back from another host
+    path: PathBuf,
+    selected: Option<usize>,
+    filter: String,
+}
+
+/// Keep at most this many places to go back to
+const MAX_NAV_HISTORY: usize = 100;
+
+/// Places visited in the browser, to go back and forward through like in a web browser
+#[derive(Default)]
+struct NavHistory {
+    back: Vec<NavEntry>,
+    forward: Vec<NavEntry>,
+}
+
+impl NavHistory {
+    /// Remember `left` as the place just moved away from. Moving somewhere new
+    /// forgets the places that could be gone forward to.
+    fn record(&mut self, left: NavEntry) {
+        self.back.push(left);
+        if self.back.len() > MAX_NAV_HISTORY {
+            self.back.remove(0);
+        }
+        self.forward.clear();
+    }
+
+    /// Where a step back (or forward) would go
+    fn peek(&self, back: bool) -> Option<&NavEntry> {
+        if back { self.back.last() } else { self.forward.last() }
+    }
+
+    /// Take a step back (or forward), with `left` becoming the place to return to
+    fn step(&mut self, back: bool, left: NavEntry) -> Option<NavEntry> {
+        let (from, to) = if back {
+            (&mut self.back, &mut self.forward)
+        } else {
+            (&mut self.forward, &mut self.back)
+        };
+        let entry = from.pop()?;
+        to.push(left);
+        Some(entry)
+    }
+}
+
+/// Stop collecting search results past this many entries
+const MAX_SEARCH_RESULTS: usize = 100_000;
+
+/// What the search thread sends back
+enum SearchEvent {
+    Found(Vec<FolderInfo>),
+    Finished(Result<(), String>),
+}
+
+/// A recursive `find` under one folder. Results stream in over `events` while
+/// it runs; dropping the receiver makes the search thread give up.
+struct RemoteSearch {
+    root: PathBuf,
+    depth: usize,
+    results: Vec<FolderInfo>, // `name` is the path relative to `root`
+    view: Vec<(i64, usize, Vec<usize>)>, // (score, index into `results`, matched char positions), best first
+    query: String,
+    list_state: ListState,
+    events: mpsc::Receiver<SearchEvent>,
+    child: Arc<Mutex<Option<Child>>>,
+    running: bool,
+    truncated: bool,
+    error: Option<String>,
+}
+
+impl RemoteSearch {
+    /// Start searching `root` on `remote_host`, at most `depth` levels down
+    fn start(remote_host: &str, root: PathBuf, depth: usize, control_socket: &str) -> Self {
+        let (sender, events) = mpsc::channel();
+        let child = Arc::new(Mutex::new(None));
+        let mut command = remote_command(remote_host, control_socket, &search_script(&root, depth));
+        let thread_child = Arc::clone(&child);
+        thread::spawn(move || {
+            let result = run_search(&mut command, &thread_child, &sender);
+            let _ = sender.send(SearchEvent::Finished(result));
+        });
+
+        RemoteSearch {
+            root,
+            depth,
+            results: Vec::new(),
+            view: Vec::new(),
+            query: String::new(),
+            list_state: ListState::default(),
+            events,
+            child,
+            running: true,
+            truncated: false,
+            error: None,
+        }
+    }
+
+    /// Take whatever the search thread has sent since the last call
+    fn poll(&mut self) {
+        let mut new_results = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                SearchEvent::Found(batch) => new_results.extend(batch),
+                SearchEvent::Finished(result) => {
+                    self.running = false;
+                    self.error = result.err();
+                }
+            }
+        }
+        if new_results.is_empty() {
+            return;
+        }
+
+        let room = MAX_SEARCH_RESULTS.saturating_sub(self.results.len());
+        if new_results.len() > room {
+            new_results.truncate(room);
+            self.truncated = true;
+            self.stop();
+        }
+        let first_new = self.results.len();
+        self.results.extend(new_results);
+        self.add_to_view(first_new);
+        if self.list_state.selected().is_none() && !self.view.is_empty() {
+            self.list_state.select(Some(0));
+        }
+    }
+
+    /// Kill the remote `find`, keeping what was found so far
+    fn stop(&mut self) {
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            let _ = child.kill();
+        }
+        self.running = false;
+    }
+
+    /// Rebuild `view` for a changed query
+    fn apply_filter(&mut self) {
+        self.view.clear();
+        self.add_to_view(0);
+    }
+
+    /// Match results from `first` on against the query and merge them into
+    /// `view`, keeping it ordered by score
+    fn add_to_view(&mut self, first: usize) {
+        use fuzzy_matcher::FuzzyMatcher;
+        use fuzzy_matcher::skim::SkimMatcherV2;
+
+        if self.query.is_empty() {
+            self.view.extend((first..self.results.len()).map(|i| (0, i, Vec::new())));
+            return;
+        }
+
+        let matcher = SkimMatcherV2::default();
+        for (i, result) in self.results.iter().enumerate().skip(first) {
+            if let Some((score, indices)) = matcher.fuzzy_indices(&result.display, &self.query) {
+                self.view.push((score, i, indices));
+            }
+        }
+        // Stable, so equal scores stay in the order find printed them
+        self.view.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+    }
+
+    fn selected(&self) -> Option<&FolderInfo> {
+        let (_, i, _) = self.view.get(self.list_state.selected()?)?;
+        self.results.get(*i)
+    }
+}
+
+impl Drop for RemoteSearch {
+    fn drop(&mut self) {
+        self.stop();
+    }
 }
 
 struct App {
     remote_host: String,
-    remote_base_path: String,
-    current_path: String,
+    remote_base_path: PathBuf, // Empty means the remote home directory, until resolved on connect
+    jail: bool,                // Don't go above remote_base_path
+    remote_home: PathBuf,      // Empty until resolved on connect
+    current_path: PathBuf,
     local_dest: String,
     ssh_control_socket: String,
 
@@ -87,67 +685,181 @@ struct App {
     // Browser tab
     folders: Vec<FolderInfo>,
     all_folders: Vec<FolderInfo>, // Unfiltered list
+    folder_matches: Vec<Vec<usize>>, // Positions of the characters the filter matched, per entry of `folders`
     browser_list_state: ListState,
     filter_query: String,
-    saved_filter_query: String, // Filter state before entering filter mode
+    saved_filter_query: String, // Filter state of the current tab before entering filter mode
+    show_details: bool,         // Size/mtime/owner/mode columns
+    show_dir_sizes: bool,       // Folder totals from `du`, which walks the whole subtree
+    marked: HashSet<OsString>,    // Names in the current folder picked for batch download
+    search: Option<RemoteSearch>, // Recursive search shown instead of the folder list
+    listing: Option<PendingListing>, // Listing in flight, shown as loading
+    completion: Option<PendingListing>, // Listing in flight for the jump prompt's Tab completion
+    next_listing_id: u64,
+    listing_sender: mpsc::Sender<ListingResponse>,
+    listing_receiver: mpsc::Receiver<ListingResponse>,
+    listing_cache: ListingCache,
+    search_depth: usize,
+    sort_mode: SortMode,
+    sort_reverse: bool,
 
     // Downloads tab
     downloads: Arc<Mutex<Vec<Download>>>,
+    queue_signal: Arc<Condvar>, // Wakes idle workers when something is queued
+    shutting_down: Arc<AtomicBool>, // Set under the downloads lock on quit; workers stop claiming
+    downloads_view: Vec<u64>, // Ids of the downloads shown, in queue order
     downloads_list_state: ListState,
+    downloads_filter_query: String,
     next_download_id: u64,
-    active_download_info: Arc<Mutex<Option<DownloadProgress>>>,
+    jobs: usize, // Number of download workers
+    retry_policy: RetryPolicy,
+    show_download_details: bool, // Popup with status and stderr of the selected download
 
     // History tab
     history: Vec<HistoryEntry>,
+    history_view: Vec<usize>, // Indices into `history` that pass the outcome and fuzzy filters
     history_list_state: ListState,
+    history_filter_query: String,
+    outcome_filter: OutcomeFilter,
+    history_file: Option<PathBuf>, // None when there is nowhere to keep it
+
+    // Bookmarks
+    bookmarks: Vec<Bookmark>,
+    bookmarks_file: Option<PathBuf>, // None when there is nowhere to keep them
+    bookmark_view: Vec<(usize, Vec<usize>)>, // Bookmarks the picker shows, with matched characters
+    bookmark_list_state: ListState,
+
+    nav_history: NavHistory,
 
     status_message: String,
 }
 
 impl App {
-    fn new(remote_source: String, local_dest: String) -> io::Result<Self> {
-        // Parse remote_source into host and path
-        let (remote_host, remote_base_path) = if let Some((host, path)) = remote_source.split_once(':') {
-            (host.to_string(), path.to_string())
+    fn new(
+        remote_source: OsString,
+        local_dest: String,
+        jobs: usize,
+        retry_policy: RetryPolicy,
+        search_depth: usize,
+        jail: bool,
+    ) -> Self {
+        // Parse remote_source into host and path, keeping the path as raw bytes
+        let source = remote_source.into_vec();
+        let (remote_host, remote_base_path) = match source.iter().position(|&b| b == b':') {
+            Some(colon) => (
+                String::from_utf8_lossy(&source[..colon]).into_owned(),
+                source[colon + 1..].to_vec(),
+            ),
+            None => (String::from_utf8_lossy(&source).into_owned(), Vec::new()),
+        };
+
+        // Remote commands start in the home directory, so a leading `~` is redundant.
+        // Dropping it keeps paths literal, as everything is quoted before reaching a shell.
+        let remote_base_path = if remote_base_path == b"~" {
+            PathBuf::new()
+        } else if let Some(rest) = remote_base_path.strip_prefix(b"~/") {
+            PathBuf::from(OsStr::from_bytes(rest))
         } else {
-            (remote_source.clone(), String::new())
+            PathBuf::from(OsString::from_vec(remote_base_path))
         };
 
         let current_path = remote_base_path.clone();
 
-        // Generate SSH control socket path
-        let ssh_control_socket = format!("/tmp/lakach-ssh-{}", std::process::id());
+        // Generate SSH control socket path. ssh replaces %C with a hash of the
+        // connection, so each host gets its own master: history entries can be
+        // downloaded again from hosts other than the one being browsed.
+        let ssh_control_socket = format!("/tmp/lakach-ssh-{}-%C", std::process::id());
 
-        let mut folders = list_remote_folders(&remote_host, &current_path, &ssh_control_socket)?;
-        folders.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        let history_file = history_file_path();
+        let mut status_message = String::new();
+        let history = match history_file.as_deref().map(load_history) {
+            Some(Ok(history)) => history,
+            Some(Err(e)) => {
+                status_message = format!("Could not read history: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let history_view: Vec<usize> = (0..history.len()).collect();
 
-        let mut browser_list_state = ListState::default();
-        if !folders.is_empty() {
-            browser_list_state.select(Some(0));
+        let bookmarks_file = bookmarks_file_path();
+        let bookmarks = match bookmarks_file.as_deref().map(load_bookmarks) {
+            Some(Ok(bookmarks)) => bookmarks,
+            Some(Err(e)) => {
+                status_message = format!("Could not read bookmarks: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let mut history_list_state = ListState::default();
+        if !history.is_empty() {
+            history_list_state.select(Some(history.len() - 1));
         }
 
-        Ok(App {
+        let (listing_sender, listing_receiver) = mpsc::channel();
+        let mut app = App {
             remote_host,
             remote_base_path,
-            current_path,
+            jail,
+            remote_home: PathBuf::new(),
+            // Stays the requested base until connecting resolves it, so that
+            // refreshing after a failed connect tries again
+            current_path: current_path.clone(),
             local_dest,
             ssh_control_socket,
             current_tab: Tab::Browser,
             input_mode: InputMode::Normal,
             input_buffer: String::new(),
-            all_folders: folders.clone(),
-            folders,
-            browser_list_state,
+            all_folders: Vec::new(),
+            folder_matches: Vec::new(),
+            folders: Vec::new(),
+            browser_list_state: ListState::default(),
             filter_query: String::new(),
             saved_filter_query: String::new(),
+            show_details: true,
+            show_dir_sizes: false,
+            marked: HashSet::new(),
+            search: None,
+            search_depth,
+            listing: None,
+            completion: None,
+            next_listing_id: 1,
+            listing_sender,
+            listing_receiver,
+            listing_cache: ListingCache::default(),
+            sort_mode: SortMode::Name,
+            sort_reverse: false,
             downloads: Arc::new(Mutex::new(Vec::new())),
+            queue_signal: Arc::new(Condvar::new()),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            downloads_view: Vec::new(),
             downloads_list_state: ListState::default(),
+            downloads_filter_query: String::new(),
             next_download_id: 1,
-            active_download_info: Arc::new(Mutex::new(None)),
-            history: Vec::new(),
-            history_list_state: ListState::default(),
-            status_message: String::new(),
-        })
+            jobs,
+            retry_policy,
+            show_download_details: false,
+            history,
+            history_view,
+            history_list_state,
+            history_filter_query: String::new(),
+            outcome_filter: OutcomeFilter::All,
+            history_file,
+            bookmarks,
+            bookmarks_file,
+            bookmark_view: Vec::new(),
+            bookmark_list_state: ListState::default(),
+            nav_history: NavHistory::default(),
+            status_message,
+        };
+        app.spawn_download_workers();
+
+        // The first listing arrives in the background like any other
+        let message = app.status_message.clone();
+        let host = app.remote_host.clone();
+        app.request_listing(current_path, ListingPurpose::Connect { host }, message);
+
+        app
     }
 
     fn next_tab(&mut self) {
@@ -166,35 +878,81 @@ impl App {
         };
     }
 
-    fn start_filtering(&mut self) {
-        if self.current_tab != Tab::Browser {
-            return;
+    /// Filter query of the current tab; each tab keeps its own
+    fn current_filter_query(&self) -> &str {
+        match (self.current_tab, &self.search) {
+            (Tab::Browser, Some(search)) => &search.query,
+            (Tab::Browser, None) => &self.filter_query,
+            (Tab::Downloads, _) => &self.downloads_filter_query,
+            (Tab::History, _) => &self.history_filter_query,
+        }
+    }
+
+    fn current_filter_query_mut(&mut self) -> &mut String {
+        match (self.current_tab, &mut self.search) {
+            (Tab::Browser, Some(search)) => &mut search.query,
+            (Tab::Browser, None) => &mut self.filter_query,
+            (Tab::Downloads, _) => &mut self.downloads_filter_query,
+            (Tab::History, _) => &mut self.history_filter_query,
         }
+    }
+
+    /// Number of rows the current tab shows
+    fn current_list_len(&self) -> usize {
+        match (self.current_tab, &self.search) {
+            (Tab::Browser, Some(search)) => search.view.len(),
+            (Tab::Browser, None) => self.folders.len(),
+            (Tab::Downloads, _) => self.downloads_view.len(),
+            (Tab::History, _) => self.history_view.len(),
+        }
+    }
+
+    fn start_filtering(&mut self) {
         // Save current filter state before entering filter mode
-        self.saved_filter_query = self.filter_query.clone();
+        self.saved_filter_query = self.current_filter_query().to_string();
         self.input_mode = InputMode::Filtering;
-        self.input_buffer = self.filter_query.clone();
+        self.input_buffer = self.saved_filter_query.clone();
         self.status_message = "Filter (Enter: confirm, Esc: cancel)".to_string();
     }
 
+    /// Re-run the current tab's filter
     fn apply_filter(&mut self) {
+        match (self.current_tab, &mut self.search) {
+            (Tab::Browser, Some(search)) => {
+                search.apply_filter();
+                search.list_state.select(if search.view.is_empty() { None } else { Some(0) });
+            }
+            (Tab::Browser, None) => self.apply_folder_filter(),
+            (Tab::Downloads, _) => {
+                self.downloads_list_state.select(Some(0));
+                self.refresh_downloads_view();
+            }
+            (Tab::History, _) => {
+                self.history_list_state.select(None);
+                self.refresh_history_view();
+            }
+        }
+    }
+
+    fn apply_folder_filter(&mut self) {
         use fuzzy_matcher::FuzzyMatcher;
         use fuzzy_matcher::skim::SkimMatcherV2;
 
         if self.filter_query.is_empty() {
             self.folders = self.all_folders.clone();
+            self.folder_matches = vec![Vec::new(); self.folders.len()];
         } else {
             let matcher = SkimMatcherV2::default();
-            let mut scored_folders: Vec<(i64, FolderInfo)> = self.all_folders
+            let mut scored_folders: Vec<(i64, Vec<usize>, FolderInfo)> = self.all_folders
                 .iter()
                 .filter_map(|folder| {
-                    matcher.fuzzy_match(&folder.name, &self.filter_query)
-                        .map(|score| (score, folder.clone()))
+                    matcher.fuzzy_indices(&folder.display, &self.filter_query)
+                        .map(|(score, indices)| (score, indices, folder.clone()))
                 })
                 .collect();
 
-            scored_folders.sort_by(|a, b| b.0.cmp(&a.0));
-            self.folders = scored_folders.into_iter().map(|(_, f)| f).collect();
+            scored_folders.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+            (self.folder_matches, self.folders) = scored_folders.into_iter().map(|(_, m, f)| (m, f)).unzip();
         }
 
         // Reset selection
@@ -202,39 +960,50 @@ impl App {
     }
 
     fn confirm_filter(&mut self) {
-        // filter_query is already set by real-time typing, just exit mode
+        // The query is already set by real-time typing, just exit mode
         self.input_mode = InputMode::Normal;
         self.input_buffer.clear();
 
-        let msg = if self.filter_query.is_empty() {
+        let msg = if self.current_filter_query().is_empty() {
             "Filter cleared".to_string()
         } else {
-            format!("Filter: {} ({} results)", self.filter_query, self.folders.len())
+            format!("Filter: {} ({} results)", self.current_filter_query(), self.current_list_len())
         };
         self.status_message = msg;
     }
 
     fn cancel_filter(&mut self) {
         // Restore previous filter state
-        self.filter_query = self.saved_filter_query.clone();
+        *self.current_filter_query_mut() = self.saved_filter_query.clone();
         self.apply_filter();
         self.input_mode = InputMode::Normal;
         self.input_buffer.clear();
 
-        let msg = if self.filter_query.is_empty() {
+        let msg = if self.current_filter_query().is_empty() {
             "Filter cancelled".to_string()
         } else {
-            format!("Filter restored: {} ({} results)", self.filter_query, self.folders.len())
+            format!("Filter restored: {} ({} results)", self.current_filter_query(), self.current_list_len())
         };
         self.status_message = msg;
     }
 
+    /// Selection and length of the list the current tab shows
+    fn current_list(&mut self) -> (&mut ListState, usize) {
+        if self.input_mode == InputMode::PickingBookmark {
+            return (&mut self.bookmark_list_state, self.bookmark_view.len());
+        }
+        match self.current_tab {
+            Tab::Browser => match &mut self.search {
+                Some(search) => (&mut search.list_state, search.view.len()),
+                None => (&mut self.browser_list_state, self.folders.len()),
+            },
+            Tab::Downloads => (&mut self.downloads_list_state, self.downloads_view.len()),
+            Tab::History => (&mut self.history_list_state, self.history_view.len()),
+        }
+    }
+
     fn page_up(&mut self) {
-        let (list_state, len) = match self.current_tab {
-            Tab::Browser => (&mut self.browser_list_state, self.folders.len()),
-            Tab::Downloads => (&mut self.downloads_list_state, self.downloads.lock().unwrap().len()),
-            Tab::History => (&mut self.history_list_state, self.history.len()),
-        };
+        let (list_state, len) = self.current_list();
 
         if len == 0 {
             return;
@@ -247,11 +1016,7 @@ impl App {
     }
 
     fn page_down(&mut self) {
-        let (list_state, len) = match self.current_tab {
-            Tab::Browser => (&mut self.browser_list_state, self.folders.len()),
-            Tab::Downloads => (&mut self.downloads_list_state, self.downloads.lock().unwrap().len()),
-            Tab::History => (&mut self.history_list_state, self.history.len()),
-        };
+        let (list_state, len) = self.current_list();
 
         if len == 0 {
             return;
@@ -272,6 +1037,7 @@ impl App {
     fn cancel_input(&mut self) {
         self.input_mode = InputMode::Normal;
         self.input_buffer.clear();
+        self.completion = None;
         self.status_message = "Cancelled".to_string();
     }
 
@@ -286,12 +1052,20 @@ impl App {
 
     fn handle_input_char(&mut self, c: char) {
         match self.input_mode {
-            InputMode::EditingPath => {
+            InputMode::EditingPath
+            | InputMode::EditingRequeueDest
+            | InputMode::ExportingHistory
+            | InputMode::MarkingGlob
+            | InputMode::JumpingToPath => {
+                self.input_buffer.push(c);
+            }
+            InputMode::PickingBookmark => {
                 self.input_buffer.push(c);
+                self.apply_bookmark_filter();
             }
             InputMode::Filtering => {
                 self.input_buffer.push(c);
-                self.filter_query = self.input_buffer.clone();
+                *self.current_filter_query_mut() = self.input_buffer.clone();
                 self.apply_filter();
             }
             InputMode::Normal => {}
@@ -300,12 +1074,20 @@ impl App {
 
     fn handle_input_backspace(&mut self) {
         match self.input_mode {
-            InputMode::EditingPath => {
+            InputMode::EditingPath
+            | InputMode::EditingRequeueDest
+            | InputMode::ExportingHistory
+            | InputMode::MarkingGlob
+            | InputMode::JumpingToPath => {
                 self.input_buffer.pop();
             }
+            InputMode::PickingBookmark => {
+                self.input_buffer.pop();
+                self.apply_bookmark_filter();
+            }
             InputMode::Filtering => {
                 self.input_buffer.pop();
-                self.filter_query = self.input_buffer.clone();
+                *self.current_filter_query_mut() = self.input_buffer.clone();
                 self.apply_filter();
             }
             InputMode::Normal => {}
@@ -313,11 +1095,7 @@ impl App {
     }
 
     fn next(&mut self) {
-        let (list_state, len) = match self.current_tab {
-            Tab::Browser => (&mut self.browser_list_state, self.folders.len()),
-            Tab::Downloads => (&mut self.downloads_list_state, self.downloads.lock().unwrap().len()),
-            Tab::History => (&mut self.history_list_state, self.history.len()),
-        };
+        let (list_state, len) = self.current_list();
 
         if len == 0 {
             return;
@@ -337,11 +1115,7 @@ impl App {
     }
 
     fn previous(&mut self) {
-        let (list_state, len) = match self.current_tab {
-            Tab::Browser => (&mut self.browser_list_state, self.folders.len()),
-            Tab::Downloads => (&mut self.downloads_list_state, self.downloads.lock().unwrap().len()),
-            Tab::History => (&mut self.history_list_state, self.history.len()),
-        };
+        let (list_state, len) = self.current_list();
 
         if len == 0 {
             return;
@@ -360,286 +1134,1788 @@ impl App {
         list_state.select(Some(i));
     }
 
-    fn enter_folder(&mut self) -> io::Result<()> {
+    fn enter_folder(&mut self) {
         if self.current_tab != Tab::Browser {
-            return Ok(());
+            return;
+        }
+        if self.search.is_some() {
+            self.jump_to_search_result();
+            return;
         }
 
-        if let Some(i) = self.browser_list_state.selected() {
-            let folder = self.folders[i].name.clone();
-
-            // Update current path
-            self.current_path = if self.current_path.is_empty() {
-                folder.clone()
-            } else {
-                format!("{}/{}", self.current_path, folder)
-            };
-
-            // List folders in the new path
-            match list_remote_folders(&self.remote_host, &self.current_path, &self.ssh_control_socket) {
-                Ok(mut folders) => {
-                    folders.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-                    self.all_folders = folders.clone();
-                    self.filter_query.clear();
-                    self.folders = folders;
-                    self.browser_list_state.select(if self.folders.is_empty() { None } else { Some(0) });
-                    self.status_message = format!("Entered: {}", folder);
-                }
-                Err(e) => {
-                    // Revert path on error
-                    let parts: Vec<&str> = self.current_path.rsplitn(2, '/').collect();
-                    self.current_path = if parts.len() > 1 {
-                        parts[1].to_string()
-                    } else {
-                        self.remote_base_path.clone()
-                    };
-                    self.status_message = format!("Error entering folder: {}", e);
-                }
+        if let Some(entry) = self.browser_list_state.selected().and_then(|i| self.folders.get(i)) {
+            if !entry.is_dir() {
+                self.status_message = format!("Not a folder: {}", entry.display);
+                return;
             }
+            let message = format!("Entered: {}", entry.display);
+            let path = self.current_path.join(&entry.name);
+            self.request_listing(path, ListingPurpose::Navigate { select: None }, message);
         }
-        Ok(())
     }
 
-    fn go_back(&mut self) -> io::Result<()> {
+    fn go_back(&mut self) {
         if self.current_tab != Tab::Browser {
-            return Ok(());
+            return;
+        }
+        if self.search.is_some() {
+            self.close_search();
+            return;
         }
 
-        // Check if we can go back
-        if self.current_path == self.remote_base_path {
-            self.status_message = "Already at base path".to_string();
-            return Ok(());
+        if self.jail && self.current_path == self.remote_base_path {
+            self.status_message = "Already at base path (--jail)".to_string();
+            return;
         }
 
         // Go up one level
-        let parts: Vec<&str> = self.current_path.rsplitn(2, '/').collect();
-        self.current_path = if parts.len() > 1 {
-            parts[1].to_string()
-        } else {
-            self.remote_base_path.clone()
+        let Some(parent) = self.current_path.parent().map(Path::to_path_buf) else {
+            self.status_message = "Already at top level".to_string();
+            return;
         };
+        // Keep the folder we came out of selected
+        let select = self.current_path.file_name().map(OsStr::to_os_string);
+        self.request_listing(parent, ListingPurpose::Navigate { select }, "Went back".to_string());
+    }
 
-        // Refresh folder list
-        match list_remote_folders(&self.remote_host, &self.current_path, &self.ssh_control_socket) {
-            Ok(mut folders) => {
-                folders.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-                self.all_folders = folders.clone();
-                self.filter_query.clear();
-                self.folders = folders;
-                self.browser_list_state.select(if self.folders.is_empty() { None } else { Some(0) });
-                self.status_message = "Went back".to_string();
-            }
-            Err(e) => {
-                self.status_message = format!("Error going back: {}", e);
-            }
+    /// List `path` in the background, replacing any listing still in flight.
+    /// Completions have a slot of their own, so pressing Tab doesn't cancel a
+    /// folder that is still loading. `message` becomes the status once it
+    /// arrives. A recent enough cached listing is used right away instead.
+    fn request_listing(&mut self, path: PathBuf, purpose: ListingPurpose, message: String) {
+        let completing = matches!(purpose, ListingPurpose::Complete);
+        if completing {
+            self.completion = None;
+        } else {
+            self.listing = None;
         }
-        Ok(())
-    }
 
-    fn queue_download(&mut self) {
-        if self.current_tab != Tab::Browser {
+        // Completions only need names
+        let with_sizes = self.show_details && self.show_dir_sizes && !completing;
+        let connect = matches!(purpose, ListingPurpose::Connect { .. });
+        let remote_host = match &purpose {
+            ListingPurpose::Connect { host } | ListingPurpose::Revisit { host, .. } => host.clone(),
+            _ => self.remote_host.clone(),
+        };
+        let key = (remote_host, path);
+        if let Some(folders) = self.listing_cache.lookup(&key, &purpose, with_sizes, Instant::now()) {
+            let folders = folders.to_vec();
+            self.apply_listing(key.1, &purpose, folders);
+            self.status_message = message;
             return;
         }
+        let (remote_host, path) = key;
 
-        if let Some(i) = self.browser_list_state.selected() {
-            let folder = &self.folders[i].name;
+        let id = self.next_listing_id;
+        self.next_listing_id += 1;
 
-            let full_path = if self.current_path.is_empty() {
-                folder.clone()
+        let child = Arc::new(Mutex::new(None));
+        let sender = self.listing_sender.clone();
+        let host = remote_host.clone();
+        let thread_path = path.clone();
+        let control_socket = self.ssh_control_socket.clone();
+        let thread_child = Arc::clone(&child);
+        thread::spawn(move || {
+            let (home, path, result) = if connect {
+                match resolve_remote_folder(&remote_host, &thread_path, &control_socket, &thread_child) {
+                    Ok((home, resolved)) => {
+                        let result = list_remote_folders(&remote_host, &resolved, &control_socket, with_sizes, &thread_child);
+                        (Some(home), resolved, result)
+                    }
+                    Err(e) => (None, thread_path, Err(e)),
+                }
             } else {
-                format!("{}/{}", self.current_path, folder)
-            };
-
-            let remote_path = format!("{}:{}", self.remote_host, full_path);
-            let download = Download {
-                id: self.next_download_id,
-                folder_name: folder.clone(),
-                remote_path: remote_path.clone(),
-                status: DownloadStatus::Queued,
-                started_at: None,
-                completed_at: None,
+                let result = list_remote_folders(&remote_host, &thread_path, &control_socket, with_sizes, &thread_child);
+                (None, thread_path, result)
             };
+            // Nobody is listening any more once lakach quits
+            let _ = sender.send(ListingResponse { id, home, path, result });
+        });
 
-            self.next_download_id += 1;
-            self.downloads.lock().unwrap().push(download);
-            self.status_message = format!("Queued: {}", folder);
+        let pending = Some(PendingListing {
+            id,
+            host,
+            path,
+            purpose,
+            message,
+            with_sizes,
+            child,
+        });
+        if completing {
+            self.completion = pending;
+        } else {
+            self.listing = pending;
+        }
+    }
 
-            // Start download worker if needed
-            self.process_download_queue();
+    /// Stop waiting for the listing in flight. Returns whether there was one.
+    fn cancel_listing(&mut self) -> bool {
+        if self.current_tab != Tab::Browser {
+            return false;
+        }
+        // Dropping it kills the ssh process
+        if self.listing.take().is_some() {
+            self.status_message = "Loading cancelled".to_string();
+            true
+        } else {
+            false
         }
     }
 
-    fn process_download_queue(&self) {
-        let downloads = Arc::clone(&self.downloads);
-        let local_dest = self.local_dest.clone();
-        let active_info = Arc::clone(&self.active_download_info);
-        let ssh_control_socket = self.ssh_control_socket.clone();
+    /// Apply the listing in flight if it has arrived. Responses to requests that
+    /// were replaced or cancelled meanwhile are dropped.
+    fn poll_listing(&mut self) {
+        while let Ok(response) = self.listing_receiver.try_recv() {
+            let pending = if self.listing.as_ref().is_some_and(|pending| pending.id == response.id) {
+                self.listing.take()
+            } else if self.completion.as_ref().is_some_and(|pending| pending.id == response.id) {
+                self.completion.take()
+            } else {
+                None
+            };
+            let Some(pending) = pending else {
+                continue;
+            };
+            if let Some(home) = response.home {
+                self.remote_home = home;
+            }
+            let folders = match response.result {
+                Ok(folders) => folders,
+                Err(e) => {
+                    self.status_message = format!(
+                        "Error loading {}: {}",
+                        display_lossy(response.path.as_os_str().as_bytes()),
+                        e.to_string().trim()
+                    );
+                    continue;
+                }
+            };
 
-        thread::spawn(move || {
-            loop {
-                let mut download_to_process = None;
+            self.listing_cache.insert(
+                (pending.host.clone(), response.path.clone()),
+                folders.clone(),
+                pending.with_sizes,
+                Instant::now(),
+            );
 
-                // Find next queued download
+            self.apply_listing(response.path, &pending.purpose, folders);
+            self.status_message = pending.message.clone();
+            if matches!(pending.purpose, ListingPurpose::Complete) && self.input_mode == InputMode::JumpingToPath {
+                self.complete_jump_path();
+            }
+        }
+    }
+
+    /// Show a listing of `path`
+    fn apply_listing(&mut self, path: PathBuf, purpose: &ListingPurpose, mut folders: Vec<FolderInfo>) {
+        sort_folders(&mut folders, self.sort_mode, self.sort_reverse);
+
+        match purpose {
+            ListingPurpose::Connect { .. } | ListingPurpose::Navigate { .. } => {
+                let host = match purpose {
+                    ListingPurpose::Connect { host } => host,
+                    _ => &self.remote_host,
+                };
+                // Nothing has been shown yet while the starting folder loads; it
+                // only gets an absolute path once resolved
+                let moved = host != &self.remote_host || path != self.current_path;
+                if moved && (self.current_path.has_root() || !self.all_folders.is_empty()) {
+                    let left = self.nav_entry();
+                    self.nav_history.record(left);
+                }
+                if let ListingPurpose::Connect { host } = purpose {
+                    self.remote_host = host.clone();
+                    self.remote_base_path = path.clone();
+                }
+                self.search = None;
+                self.marked.clear();
+                self.current_path = path;
+                self.all_folders = folders;
+                self.filter_query.clear();
+                self.apply_folder_filter();
+                if let ListingPurpose::Navigate { select: Some(name) } = purpose
+                    && let Some(pos) = self.folders.iter().position(|f| &f.name == name)
                 {
+                    self.browser_list_state.select(Some(pos));
+                }
+            }
+            ListingPurpose::Reload => {
+                // Only if the user hasn't moved on; otherwise the listing is stale
+                if path == self.current_path {
+                    self.marked.retain(|name| folders.iter().any(|f| &f.name == name));
+                    self.all_folders = folders;
+                    self.resort_folders();
+                }
+            }
+            ListingPurpose::Revisit { back, .. } => {
+                let left = self.nav_entry();
+                let Some(entry) = self.nav_history.step(*back, left) else {
+                    return;
+                };
+                if entry.host != self.remote_host {
+                    self.remote_host = entry.host;
+                    self.remote_home = entry.home;
+                    self.remote_base_path = entry.base;
+                }
+                self.search = None;
+                self.marked.clear();
+                self.current_path = path;
+                self.all_folders = folders;
+                self.filter_query = entry.filter;
+                self.apply_folder_filter();
+                if let Some(selected) = entry.selected
+                    && !self.folders.is_empty()
+                {
+                    self.browser_list_state.select(Some(selected.min(self.folders.len() - 1)));
+                }
+            }
+            ListingPurpose::Complete => {}
+        }
+    }
+
+    /// The folder shown now, to come back to later
+    fn nav_entry(&self) -> NavEntry {
+        NavEntry {
+            host: self.remote_host.clone(),
+            home: self.remote_home.clone(),
+            base: self.remote_base_path.clone(),
+            path: self.current_path.clone(),
+            selected: self.browser_list_state.selected(),
+            filter: self.filter_query.clone(),
+        }
+    }
+
+    /// Go back (or forward) to the previous (or next) folder in the navigation
+    /// history, with the selection and filter it had
+    fn step_nav_history(&mut self, back: bool) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+        let Some(entry) = self.nav_history.peek(back) else {
+            self.status_message = if back { "Nothing to go back to" } else { "Nothing to go forward to" }.to_string();
+            return;
+        };
+        let (host, path) = (entry.host.clone(), entry.path.clone());
+        let message = format!(
+            "{} to {}:{}",
+            if back { "Back" } else { "Forward" },
+            host,
+            display_lossy(path.as_os_str().as_bytes())
+        );
+        self.request_listing(path, ListingPurpose::Revisit { host, back }, message);
+    }
+
+    /// Ask for a remote folder to go to, starting from the current one
+    fn start_jump_to_path(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+        let current = display_lossy(self.current_path.as_os_str().as_bytes());
+        self.input_buffer = if current.is_empty() {
+            "~/".to_string()
+        } else if current.starts_with('/') {
+            format!("{}/", current.trim_end_matches('/'))
+        } else {
+            format!("~/{}/", current)
+        };
+        self.input_mode = InputMode::JumpingToPath;
+        self.status_message = "Go to a folder (Tab: complete, Enter: go, Esc: cancel)".to_string();
+    }
+
+    /// Why `path` on the current host may not be visited under `--jail`, if it
+    /// may not. Until connecting has resolved the base path there is nothing
+    /// to check against, so everything is refused.
+    fn jail_refusal(&self, path: &Path, label: &str) -> Option<String> {
+        if !self.jail {
+            None
+        } else if !self.remote_base_path.has_root() {
+            Some(format!("Can't go to {} before the base path has loaded (--jail)", label))
+        } else if !path.starts_with(&self.remote_base_path) {
+            Some(format!("{} is outside the base path (--jail)", label))
+        } else {
+            None
+        }
+    }
+
+    /// Go to the folder typed into the jump prompt. The listing doubles as the
+    /// check that it is a folder; if it fails the browser stays where it is.
+    fn confirm_jump_to_path(&mut self) {
+        self.input_mode = InputMode::Normal;
+        self.completion = None;
+        let input = std::mem::take(&mut self.input_buffer);
+        if input.trim().is_empty() {
+            self.status_message = "Cancelled".to_string();
+            return;
+        }
+        let path = resolve_remote_path(&self.current_path, &self.remote_home, input.trim());
+        if let Some(refusal) = self.jail_refusal(&path, input.trim()) {
+            self.status_message = refusal;
+            return;
+        }
+        self.search = None;
+        let message = format!("Went to {}", input.trim());
+        self.request_listing(path, ListingPurpose::Navigate { select: None }, message);
+    }
+
+    /// Complete the last component of the jump prompt from the folders in its
+    /// parent. Lists the parent in the background first if it isn't cached;
+    /// completion resumes when the listing arrives.
+    fn complete_jump_path(&mut self) {
+        let (dir_part, partial) = match self.input_buffer.rfind('/') {
+            Some(i) => self.input_buffer.split_at(i + 1),
+            None => ("", self.input_buffer.as_str()),
+        };
+        let (dir_part, partial) = (dir_part.to_string(), partial.to_string());
+        let dir = resolve_remote_path(&self.current_path, &self.remote_home, if dir_part.is_empty() { "." } else { &dir_part });
+
+        let key = (self.remote_host.clone(), dir.clone());
+        let cached = self.listing_cache.lookup(&key, &ListingPurpose::Complete, false, Instant::now());
+        let folders = if dir == self.current_path && self.search.is_none() {
+            &self.all_folders
+        } else if let Some(folders) = cached {
+            folders
+        } else {
+            self.request_listing(dir, ListingPurpose::Complete, "Completing...".to_string());
+            self.status_message = "Completing...".to_string();
+            return;
+        };
+
+        // Names that aren't valid UTF-8 can't be typed back, so they aren't offered
+        let mut candidates: Vec<&str> = folders
+            .iter()
+            .filter(|f| f.is_dir())
+            .filter_map(|f| f.name.to_str())
+            .filter(|name| name.starts_with(partial.as_str()))
+            .collect();
+        candidates.sort_unstable();
+
+        match candidates.as_slice() {
+            [] => self.status_message = format!("No folders matching {}", partial),
+            [only] => {
+                self.input_buffer = format!("{}{}/", dir_part, only);
+                self.status_message = "Go to a folder (Tab: complete, Enter: go, Esc: cancel)".to_string();
+            }
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(*first, |common, name| common_prefix(common, name));
+                self.input_buffer = format!("{}{}", dir_part, common);
+                let shown: Vec<&str> = candidates.iter().take(8).copied().collect();
+                let more = if candidates.len() > shown.len() { " ..." } else { "" };
+                self.status_message = format!("{} matches: {}{}", candidates.len(), shown.join("  "), more);
+            }
+        }
+    }
+
+    /// Fetch the current folder again, bypassing the cache
+    fn refresh_listing(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        self.listing_cache.invalidate(&(self.remote_host.clone(), self.current_path.clone()));
+        // Try resolving the starting folder again if that failed the first time
+        let purpose = if self.current_path == self.remote_base_path && !self.remote_base_path.has_root() {
+            ListingPurpose::Connect {
+                host: self.remote_host.clone(),
+            }
+        } else {
+            ListingPurpose::Reload
+        };
+        self.request_listing(self.current_path.clone(), purpose, "Refreshed".to_string());
+    }
+
+    /// Start a recursive search under the current folder and go straight to
+    /// narrowing its results
+    fn start_search(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        let root = self.current_path.clone();
+        self.search = Some(RemoteSearch::start(&self.remote_host, root, self.search_depth, &self.ssh_control_socket));
+        self.saved_filter_query.clear();
+        self.input_mode = InputMode::Filtering;
+        self.input_buffer.clear();
+        self.status_message = format!("Searching {} levels deep (Enter: confirm, Esc: cancel)", self.search_depth);
+    }
+
+    /// Stop a running search but keep its results
+    fn stop_search(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+        if let Some(search) = &mut self.search
+            && search.running
+        {
+            search.stop();
+            self.status_message = format!("Search stopped: {} results", search.results.len());
+        }
+    }
+
+    /// Leave the search results, going back to the folder list. Dropping the
+    /// search kills it if it is still running.
+    fn close_search(&mut self) {
+        if self.current_tab == Tab::Browser && self.search.take().is_some() {
+            self.status_message = "Search closed".to_string();
+        }
+    }
+
+    /// Open the folder containing the selected search result, with the result selected
+    fn jump_to_search_result(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let Some(entry) = search.selected() else {
+            return;
+        };
+        let full_path = search.root.join(&entry.name);
+        let (Some(parent), Some(name)) = (full_path.parent(), full_path.file_name()) else {
+            return;
+        };
+        let message = format!("Jumped to: {}", display_lossy(parent.as_os_str().as_bytes()));
+        let purpose = ListingPurpose::Navigate {
+            select: Some(name.to_os_string()),
+        };
+        // The search closes once the folder has loaded
+        self.request_listing(parent.to_path_buf(), purpose, message);
+    }
+
+    fn cycle_sort_mode(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        self.sort_mode = self.sort_mode.next();
+        self.resort_folders();
+        self.status_message = format!("Sort: {}", self.sort_mode.label());
+    }
+
+    fn toggle_sort_reverse(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        self.sort_reverse = !self.sort_reverse;
+        self.resort_folders();
+        self.status_message = if self.sort_reverse {
+            format!("Sort: {} (reversed)", self.sort_mode.label())
+        } else {
+            format!("Sort: {}", self.sort_mode.label())
+        };
+    }
+
+    fn resort_folders(&mut self) {
+        // Keep the cursor on the same entry after reordering
+        let selected = self
+            .browser_list_state
+            .selected()
+            .and_then(|i| self.folders.get(i))
+            .map(|f| f.name.clone());
+
+        sort_folders(&mut self.all_folders, self.sort_mode, self.sort_reverse);
+        self.apply_folder_filter();
+
+        if let Some(name) = selected
+            && let Some(pos) = self.folders.iter().position(|f| f.name == name)
+        {
+            self.browser_list_state.select(Some(pos));
+        }
+    }
+
+    fn toggle_details(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        self.show_details = !self.show_details;
+        if !self.show_details {
+            self.status_message = "Details hidden".to_string();
+        } else if self.show_dir_sizes {
+            // Folder sizes are only computed while the columns are visible
+            self.fetch_folder_sizes("Details shown");
+        } else {
+            self.status_message = "Details shown".to_string();
+        }
+    }
+
+    /// Turn folder totals on or off. They come from a recursive `du`, which can
+    /// take a long time on big trees, so they are off until asked for.
+    fn toggle_dir_sizes(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        self.show_dir_sizes = !self.show_dir_sizes;
+        if !self.show_dir_sizes {
+            self.status_message = "Folder sizes off".to_string();
+            return;
+        }
+        self.show_details = true;
+        self.fetch_folder_sizes("Folder sizes on");
+    }
+
+    /// List the current folder again with folder sizes. A listing already in
+    /// flight is restarted so it includes them.
+    fn fetch_folder_sizes(&mut self, message: &str) {
+        match self.listing.take() {
+            Some(pending) => {
+                self.request_listing(pending.path.clone(), pending.purpose.clone(), pending.message.clone());
+            }
+            None => {
+                self.request_listing(self.current_path.clone(), ListingPurpose::Reload, message.to_string());
+            }
+        }
+    }
+
+    fn queue_download(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+
+        // The selected search result or folder entry, with its full remote path
+        let selected = match &self.search {
+            Some(search) => search.selected().map(|entry| (entry, search.root.join(&entry.name))),
+            None => self
+                .browser_list_state
+                .selected()
+                .and_then(|i| self.folders.get(i))
+                .map(|entry| (entry, self.current_path.join(&entry.name))),
+        };
+        if let Some((entry, remote_path)) = selected {
+            let (folder_name, kind) = (entry.display.clone(), entry.kind);
+            self.push_download(folder_name, self.remote_host.clone(), remote_path, self.local_dest.clone(), kind);
+        }
+    }
+
+    /// Mark or unmark the selected entry and move to the next one
+    fn toggle_mark(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        if let Some(entry) = self.browser_list_state.selected().and_then(|i| self.folders.get(i)) {
+            if !self.marked.remove(&entry.name) {
+                self.marked.insert(entry.name.clone());
+            }
+            self.next();
+        }
+    }
+
+    /// Mark every entry the filter shows
+    fn mark_all(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        self.marked.extend(self.folders.iter().map(|f| f.name.clone()));
+        self.status_message = format!("Marked: {}", self.marked.len());
+    }
+
+    /// Flip the marks of every entry the filter shows
+    fn invert_marks(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        for entry in &self.folders {
+            if !self.marked.remove(&entry.name) {
+                self.marked.insert(entry.name.clone());
+            }
+        }
+        self.status_message = format!("Marked: {}", self.marked.len());
+    }
+
+    fn start_mark_glob(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        self.input_mode = InputMode::MarkingGlob;
+        self.input_buffer.clear();
+        self.status_message = "Mark entries matching a pattern such as *S01* (Enter: mark, Esc: cancel)".to_string();
+    }
+
+    /// Mark every entry in the folder whose name matches the pattern in `input_buffer`
+    fn confirm_mark_glob(&mut self) {
+        self.input_mode = InputMode::Normal;
+        let pattern = std::mem::take(&mut self.input_buffer);
+        if pattern.is_empty() {
+            self.status_message = "Cancelled".to_string();
+            return;
+        }
+        let matching: Vec<OsString> = self
+            .all_folders
+            .iter()
+            .filter(|f| glob_match(&pattern, &f.display))
+            .map(|f| f.name.clone())
+            .collect();
+        self.status_message = format!("Marked {} entries matching {}", matching.len(), pattern);
+        self.marked.extend(matching);
+    }
+
+    /// Queue every marked entry, in listing order, and clear the marks
+    fn queue_marked(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        if self.marked.is_empty() {
+            self.status_message = "Nothing marked (Space marks the selected entry)".to_string();
+            return;
+        }
+
+        let entries: Vec<(String, PathBuf, EntryKind)> = self
+            .all_folders
+            .iter()
+            .filter(|f| self.marked.contains(&f.name))
+            .map(|f| (f.display.clone(), self.current_path.join(&f.name), f.kind))
+            .collect();
+        for (folder_name, remote_path, kind) in &entries {
+            self.push_download(
+                folder_name.clone(),
+                self.remote_host.clone(),
+                remote_path.clone(),
+                self.local_dest.clone(),
+                *kind,
+            );
+        }
+        self.marked.clear();
+        self.status_message = format!("Queued {} downloads -> {}", entries.len(), self.local_dest);
+    }
+
+    /// Download the selected history entry again, to `local_dest` or to where it went last time
+    fn requeue_history_item(&mut self, local_dest: Option<String>) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+
+        if let Some(entry) = self.selected_history_index().map(|i| &self.history[i]) {
+            let local_dest = local_dest.unwrap_or_else(|| entry.local_dest.clone());
+            self.push_download(
+                entry.folder_name.clone(),
+                entry.remote_host.clone(),
+                entry.remote_path.clone(),
+                local_dest,
+                entry.kind,
+            );
+        }
+    }
+
+    fn start_requeue_with_destination(&mut self) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+
+        if let Some(entry) = self.selected_history_index().map(|i| &self.history[i]) {
+            self.input_mode = InputMode::EditingRequeueDest;
+            self.input_buffer = entry.local_dest.clone();
+            self.status_message = format!("Download {} to (Enter: queue, Esc: cancel)", entry.folder_name);
+        }
+    }
+
+    fn confirm_requeue_destination(&mut self) {
+        self.input_mode = InputMode::Normal;
+        let local_dest = std::mem::take(&mut self.input_buffer);
+        if local_dest.is_empty() {
+            self.status_message = "Cancelled".to_string();
+        } else {
+            self.requeue_history_item(Some(local_dest));
+        }
+    }
+
+    fn push_download(
+        &mut self,
+        folder_name: String,
+        remote_host: String,
+        remote_path: PathBuf,
+        local_dest: String,
+        kind: EntryKind,
+    ) {
+        self.status_message = format!("Queued: {} -> {}", folder_name, local_dest);
+        let download = Download::queued(self.next_download_id, folder_name, remote_host, remote_path, local_dest, kind);
+        self.next_download_id += 1;
+        self.downloads.lock().unwrap().push(download);
+        self.queue_signal.notify_one();
+        self.refresh_downloads_view();
+    }
+
+    /// Start the fixed pool of download workers. Each worker takes the next queued
+    /// download, runs rsync for it and sleeps on `queue_signal` while the queue is empty.
+    fn spawn_download_workers(&self) {
+        for _ in 0..self.jobs {
+            let downloads = Arc::clone(&self.downloads);
+            let queue_signal = Arc::clone(&self.queue_signal);
+            let shutting_down = Arc::clone(&self.shutting_down);
+            let ssh_control_socket = self.ssh_control_socket.clone();
+            let retry_policy = self.retry_policy;
+
+            thread::spawn(move || {
+                loop {
+                    // Claim the next queued download, waiting until there is one.
+                    // Downloads waiting to be retried only count once their delay is up.
+                    let download = {
+                        let mut downloads_lock = downloads.lock().unwrap();
+                        loop {
+                            // Checked under the lock, so nothing is claimed once quitting has begun
+                            if shutting_down.load(AtomicOrdering::SeqCst) {
+                                return;
+                            }
+                            let now = unix_now();
+                            if let Some(download) = downloads_lock
+                                .iter_mut()
+                                .find(|d| d.status == DownloadStatus::Queued && d.retry_at.is_none_or(|t| t <= now))
+                            {
+                                download.status = DownloadStatus::Downloading;
+                                // Keep the original start time when resuming
+                                download.started_at.get_or_insert(now);
+                                download.retry_at = None;
+                                download.stderr_tail.clear();
+                                break download.clone();
+                            }
+
+                            // Sleep until woken, or until the next retry is due
+                            let next_retry = downloads_lock
+                                .iter()
+                                .filter(|d| d.status == DownloadStatus::Queued)
+                                .filter_map(|d| d.retry_at)
+                                .min();
+                            downloads_lock = match next_retry {
+                                Some(t) => {
+                                    let timeout = Duration::from_secs(t.saturating_sub(now).max(1));
+                                    queue_signal.wait_timeout(downloads_lock, timeout).unwrap().0
+                                }
+                                None => queue_signal.wait(downloads_lock).unwrap(),
+                            };
+                        }
+                    };
+
+                    let result = run_rsync(&download, &ssh_control_socket, &downloads);
+
+                    // Update status, unless the download was cancelled or paused meanwhile
                     let mut downloads_lock = downloads.lock().unwrap();
-                    for download in downloads_lock.iter_mut() {
-                        if download.status == DownloadStatus::Queued {
-                            download.status = DownloadStatus::Downloading;
-                            download.started_at = Some(
-                                SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                            );
-                            download_to_process = Some(download.clone());
-                            break;
+                    if let Some(d) = downloads_lock.iter_mut().find(|d| d.id == download.id)
+                        && d.status == DownloadStatus::Downloading
+                    {
+                        if d.pause_requested {
+                            // rsync has exited, so the partial file is in place for resuming
+                            d.pause_requested = false;
+                            d.status = DownloadStatus::Paused;
+                            d.resume = true;
+                            continue;
+                        }
+                        match result.map(|status| status.code()) {
+                            Ok(Some(0)) => {
+                                d.status = DownloadStatus::Completed;
+                                d.completed_at = Some(unix_now());
+                            }
+                            Ok(Some(code))
+                                if RETRYABLE_EXIT_CODES.contains(&code) && d.attempt < retry_policy.max_retries =>
+                            {
+                                d.attempt += 1;
+                                d.retry_at = Some(unix_now() + retry_policy.delay(d.attempt));
+                                d.status = DownloadStatus::Queued;
+                            }
+                            Ok(Some(code)) => {
+                                d.status = DownloadStatus::Failed(failure_reason(code, &d.stderr_tail));
+                                d.completed_at = Some(unix_now());
+                            }
+                            Ok(None) => {
+                                d.status = DownloadStatus::Failed("rsync was killed by a signal".to_string());
+                                d.completed_at = Some(unix_now());
+                            }
+                            Err(e) => {
+                                d.status = DownloadStatus::Failed(format!("could not start rsync: {}", e));
+                                d.completed_at = Some(unix_now());
+                            }
                         }
                     }
                 }
+            });
+        }
+    }
+
+    fn cancel_download(&mut self) {
+        if self.current_tab != Tab::Downloads {
+            return;
+        }
+        let Some(idx) = self.selected_download_index() else {
+            return;
+        };
+
+        if let Some(message) = cancel_download_at(&mut self.downloads.lock().unwrap(), idx) {
+            self.status_message = message;
+        }
+        self.refresh_downloads_view();
+    }
+
+    fn toggle_pause_download(&mut self) {
+        if self.current_tab != Tab::Downloads {
+            return;
+        }
+        let Some(idx) = self.selected_download_index() else {
+            return;
+        };
+
+        let mut downloads = self.downloads.lock().unwrap();
+        let Some(download) = downloads.get_mut(idx) else {
+            return;
+        };
+        match download.status {
+            DownloadStatus::Queued => {
+                // Hold it back so no worker picks it up
+                download.status = DownloadStatus::Paused;
+                self.status_message = format!("Paused: {}", download.folder_name);
+            }
+            DownloadStatus::Downloading if download.pause_requested => {
+                self.status_message = format!("Already pausing: {}", download.folder_name);
+            }
+            DownloadStatus::Downloading => {
+                // Stop rsync so it keeps the partial file it is writing. The worker
+                // marks the download paused once rsync has exited.
+                download.pause_requested = true;
+                if let Some(child) = &download.child {
+                    let _ = terminate(&mut child.lock().unwrap());
+                }
+                self.status_message = format!("Pausing: {}", download.folder_name);
+            }
+            DownloadStatus::Paused => {
+                download.status = DownloadStatus::Queued;
+                self.status_message = format!("Resumed: {}", download.folder_name);
+                self.queue_signal.notify_one();
+            }
+            _ => {
+                self.status_message = "Only queued or running downloads can be paused".to_string();
+            }
+        }
+    }
 
-                if let Some(download) = download_to_process {
-                    // Run rsync with piped output and --info=progress2 for machine-readable progress
-                    // Use SSH ControlMaster for connection reuse
-                    let ssh_cmd = format!(
-                        "ssh -o ControlMaster=auto -o ControlPath={} -o ControlPersist=600",
-                        ssh_control_socket
-                    );
-                    let mut child = Command::new("rsync")
-                        .arg("-vrtzhP")
-                        .arg("--info=progress2")
-                        .arg("-e")
-                        .arg(&ssh_cmd)
-                        .arg(&download.remote_path)
-                        .arg(&local_dest)
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .spawn();
-
-                    let success = if let Ok(ref mut child_process) = child {
-                        // Spawn thread to read and parse stderr (where progress goes)
-                        if let Some(stderr) = child_process.stderr.take() {
-                            let info_clone = Arc::clone(&active_info);
-                            thread::spawn(move || {
-                                let reader = BufReader::new(stderr);
-                                let mut current_file = String::new();
-
-                                for line in reader.lines().flatten() {
-                                    // Parse rsync output
-                                    let parsed = parse_rsync_line(&line, &mut current_file);
-                                    if let Some(info) = parsed {
-                                        *info_clone.lock().unwrap() = Some(info);
-                                    }
-                                }
-                            });
-                        }
+    fn toggle_download_details(&mut self) {
+        if self.current_tab != Tab::Downloads {
+            return;
+        }
+        self.show_download_details = !self.show_download_details;
+    }
+
+    /// Queue a failed download again, with a fresh set of retries
+    fn retry_download(&mut self) {
+        if self.current_tab != Tab::Downloads {
+            return;
+        }
+        let Some(idx) = self.selected_download_index() else {
+            return;
+        };
+
+        let mut downloads = self.downloads.lock().unwrap();
+        let Some(download) = downloads.get_mut(idx) else {
+            return;
+        };
+        match download.status {
+            DownloadStatus::Failed(_) => {
+                download.status = DownloadStatus::Queued;
+                download.attempt = 0;
+                download.retry_at = None;
+                download.progress = None;
+                download.recorded = false;
+                download.started_at = None;
+                download.completed_at = None;
+                self.status_message = format!("Retrying: {}", download.folder_name);
+                self.queue_signal.notify_one();
+            }
+            _ => {
+                self.status_message = "Only failed downloads can be retried".to_string();
+            }
+        }
+    }
+
+    /// Kill every running rsync so none outlive lakach
+    fn kill_running_downloads(&self) {
+        let mut downloads = self.downloads.lock().unwrap();
+        self.shutting_down.store(true, AtomicOrdering::SeqCst);
+        self.queue_signal.notify_all();
+        for download in downloads.iter_mut() {
+            if download.status == DownloadStatus::Downloading {
+                download.status = DownloadStatus::Cancelled;
+            }
+            if let Some(child) = download.child.take() {
+                let _ = child.lock().unwrap().kill();
+            }
+        }
+    }
+
+    /// Record downloads that have ended in history. Completed and cancelled ones
+    /// leave the Downloads list; failed ones stay so they can be inspected and
+    /// retried, until dismissed with `x`.
+    fn record_finished_downloads(&mut self) {
+        let recorded = record_finished(&mut self.downloads.lock().unwrap());
+        if recorded.is_empty() {
+            return;
+        }
+        for entry in recorded {
+            if let Some(path) = &self.history_file
+                && let Err(e) = append_history(path, &entry)
+            {
+                self.status_message = format!("Could not save history: {}", e);
+            }
+            self.history.push(entry);
+        }
+        self.refresh_history_view();
+    }
+
+    /// Remove a failed download from the Downloads list; it stays in history
+    fn dismiss_download(&mut self) {
+        if self.current_tab != Tab::Downloads {
+            return;
+        }
+        let Some(idx) = self.selected_download_index() else {
+            return;
+        };
+
+        let mut downloads = self.downloads.lock().unwrap();
+        match downloads.get(idx).map(|d| &d.status) {
+            Some(DownloadStatus::Failed(_)) => {
+                let removed = downloads.remove(idx);
+                self.status_message = format!("Dismissed: {}", removed.folder_name);
+                self.show_download_details = false;
+            }
+            Some(_) => {
+                self.status_message = "Only failed downloads can be dismissed".to_string();
+            }
+            None => {}
+        }
+        drop(downloads);
+        self.refresh_downloads_view();
+    }
+
+    /// Position in the downloads list of the selected row
+    fn selected_download_index(&self) -> Option<usize> {
+        let id = self.downloads_list_state.selected().and_then(|i| self.downloads_view.get(i))?;
+        self.downloads.lock().unwrap().iter().position(|d| d.id == *id)
+    }
+
+    /// Recompute which downloads are shown. Workers change the list at any
+    /// time, so this runs before every frame as well as after edits.
+    fn refresh_downloads_view(&mut self) {
+        let downloads = self.downloads.lock().unwrap();
+        self.downloads_view = downloads
+            .iter()
+            .filter(|d| {
+                let source = format!("{}:{}", d.remote_host, display_lossy(d.remote_path.as_os_str().as_bytes()));
+                fuzzy_matches_any(&self.downloads_filter_query, &[&d.folder_name, &source])
+            })
+            .map(|d| d.id)
+            .collect();
+        drop(downloads);
+
+        // Keep the selection in range
+        if self.downloads_view.is_empty() {
+            self.downloads_list_state.select(None);
+        } else if self.downloads_list_state.selected().is_some_and(|i| i >= self.downloads_view.len()) {
+            self.downloads_list_state.select(Some(self.downloads_view.len() - 1));
+        }
+    }
+
+    /// Index into `history` of the selected row
+    fn selected_history_index(&self) -> Option<usize> {
+        self.history_list_state.selected().and_then(|i| self.history_view.get(i).copied())
+    }
+
+    /// Recompute which history entries are shown after the history or the
+    /// outcome filter changed
+    fn refresh_history_view(&mut self) {
+        self.history_view = shown_history(&self.history, self.outcome_filter, &self.history_filter_query);
+
+        // Keep the selection in range
+        if self.history_view.is_empty() {
+            self.history_list_state.select(None);
+        } else {
+            let last = self.history_view.len() - 1;
+            self.history_list_state
+                .select(Some(self.history_list_state.selected().map_or(last, |i| i.min(last))));
+        }
+    }
+
+    fn cycle_outcome_filter(&mut self) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+        self.outcome_filter = self.outcome_filter.next();
+        self.history_list_state.select(None);
+        self.refresh_history_view();
+        self.status_message = format!("Showing {} downloads", self.outcome_filter.label());
+    }
+
+    fn clear_history_item(&mut self) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+
+        if let Some(idx) = self.selected_history_index() {
+            let removed = self.history.remove(idx);
+            self.status_message = format!("Removed: {}", removed.folder_name);
+            self.save_history();
+            self.refresh_history_view();
+        }
+    }
+
+    /// Clear every entry the outcome filter currently shows
+    fn clear_all_history(&mut self) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+
+        // Only what is shown; entries hidden by either filter stay
+        let count = self.history_view.len();
+        remove_indices(&mut self.history, &self.history_view);
+        self.history_list_state.select(None);
+        self.refresh_history_view();
+        self.status_message = format!("Cleared {} history items", count);
+        self.save_history();
+    }
+
+    fn start_exporting_history(&mut self) {
+        if self.current_tab != Tab::History {
+            return;
+        }
+        self.input_mode = InputMode::ExportingHistory;
+        self.input_buffer = "lakach-history.jsonl".to_string();
+        self.status_message = "Export history to (.csv for CSV, JSON Lines otherwise)".to_string();
+    }
+
+    /// Write the entries the History tab shows to the file in `input_buffer`
+    fn confirm_export_history(&mut self) {
+        self.input_mode = InputMode::Normal;
+        let path = PathBuf::from(std::mem::take(&mut self.input_buffer));
+        if path.as_os_str().is_empty() {
+            self.status_message = "Cancelled".to_string();
+            return;
+        }
+
+        let format = ExportFormat::from_path(&path);
+        let entries = self.history_view.iter().map(|&i| &self.history[i]);
+        let result = fs::File::create(&path).and_then(|file| {
+            let mut out = io::BufWriter::new(file);
+            export_history(entries, format, &mut out)?;
+            out.flush()
+        });
+        self.status_message = match result {
+            Ok(()) => format!("Exported {} entries to {}", self.history_view.len(), path.display()),
+            Err(e) => format!("Could not export history: {}", e),
+        };
+    }
+
+    /// Rewrite the history file after entries were removed
+    fn save_history(&mut self) {
+        if let Some(path) = &self.history_file
+            && let Err(e) = write_history(path, &self.history)
+        {
+            self.status_message = format!("Could not save history: {}", e);
+        }
+    }
+
+    /// Bookmark the current folder, or remove its bookmark if it has one
+    fn toggle_bookmark(&mut self) {
+        if self.current_tab != Tab::Browser || self.search.is_some() {
+            return;
+        }
+        let bookmark = Bookmark {
+            host: self.remote_host.clone(),
+            path: self.current_path.clone(),
+        };
+        if let Some(pos) = self.bookmarks.iter().position(|b| *b == bookmark) {
+            self.bookmarks.remove(pos);
+            self.status_message = format!("Removed bookmark {}", bookmark.label());
+        } else {
+            self.status_message = format!("Bookmarked {}", bookmark.label());
+            self.bookmarks.push(bookmark);
+        }
+        self.save_bookmarks();
+    }
+
+    fn open_bookmarks(&mut self) {
+        if self.current_tab != Tab::Browser {
+            return;
+        }
+        if self.bookmarks.is_empty() {
+            self.status_message = "No bookmarks yet (b bookmarks the current folder)".to_string();
+            return;
+        }
+        self.input_mode = InputMode::PickingBookmark;
+        self.input_buffer.clear();
+        self.apply_bookmark_filter();
+        self.status_message = "Pick a bookmark (type to filter, Enter: go, Del: remove, Esc: cancel)".to_string();
+    }
+
+    /// Show the bookmarks matching `input_buffer`, best match first, or all of
+    /// them in the order they were added
+    fn apply_bookmark_filter(&mut self) {
+        use fuzzy_matcher::FuzzyMatcher;
+        use fuzzy_matcher::skim::SkimMatcherV2;
+
+        if self.input_buffer.is_empty() {
+            self.bookmark_view = (0..self.bookmarks.len()).map(|i| (i, Vec::new())).collect();
+        } else {
+            let matcher = SkimMatcherV2::default();
+            let mut scored: Vec<(i64, usize, Vec<usize>)> = self
+                .bookmarks
+                .iter()
+                .enumerate()
+                .filter_map(|(i, bookmark)| {
+                    let (score, indices) = matcher.fuzzy_indices(&bookmark.label(), &self.input_buffer)?;
+                    Some((score, i, indices))
+                })
+                .collect();
+            scored.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+            self.bookmark_view = scored.into_iter().map(|(_, i, indices)| (i, indices)).collect();
+        }
+        self.bookmark_list_state.select(if self.bookmark_view.is_empty() { None } else { Some(0) });
+    }
+
+    fn selected_bookmark_index(&self) -> Option<usize> {
+        let (i, _) = self.bookmark_view.get(self.bookmark_list_state.selected()?)?;
+        Some(*i)
+    }
+
+    /// Go to the bookmark picked in the picker, connecting to its host if it is
+    /// on another one
+    fn confirm_bookmark(&mut self) {
+        let Some(i) = self.selected_bookmark_index() else {
+            self.status_message = "No bookmark matches".to_string();
+            return;
+        };
+        self.input_mode = InputMode::Normal;
+        self.input_buffer.clear();
+
+        let bookmark = self.bookmarks[i].clone();
+        if self.jail && bookmark.host != self.remote_host {
+            self.status_message = format!("{} is outside the base path (--jail)", bookmark.label());
+            return;
+        }
+        if let Some(refusal) = self.jail_refusal(&bookmark.path, &bookmark.label()) {
+            self.status_message = refusal;
+            return;
+        }
+        self.search = None;
+        let message = format!("Went to {}", bookmark.label());
+        let purpose = if bookmark.host == self.remote_host {
+            ListingPurpose::Navigate { select: None }
+        } else {
+            ListingPurpose::Connect { host: bookmark.host }
+        };
+        self.request_listing(bookmark.path, purpose, message);
+    }
+
+    /// Delete the bookmark selected in the picker
+    fn remove_selected_bookmark(&mut self) {
+        let Some(i) = self.selected_bookmark_index() else {
+            return;
+        };
+        let removed = self.bookmarks.remove(i);
+        self.status_message = format!("Removed bookmark {}", removed.label());
+        self.save_bookmarks();
+
+        if self.bookmarks.is_empty() {
+            self.input_mode = InputMode::Normal;
+            self.input_buffer.clear();
+            return;
+        }
+        let selected = self.bookmark_list_state.selected().unwrap_or(0);
+        self.apply_bookmark_filter();
+        if !self.bookmark_view.is_empty() {
+            self.bookmark_list_state.select(Some(selected.min(self.bookmark_view.len() - 1)));
+        }
+    }
+
+    fn save_bookmarks(&mut self) {
+        if let Some(path) = &self.bookmarks_file
+            && let Err(e) = write_bookmarks(path, &self.bookmarks)
+        {
+            self.status_message = format!("Could not save bookmarks: {}", e);
+        }
+    }
+}
+
+/// Indices into `history` of the entries that pass the outcome filter and the
+/// fuzzy query, in order
+fn shown_history(history: &[HistoryEntry], outcome_filter: OutcomeFilter, query: &str) -> Vec<usize> {
+    (0..history.len())
+        .filter(|&i| {
+            let h = &history[i];
+            let source = format!("{}:{}", h.remote_host, display_lossy(h.remote_path.as_os_str().as_bytes()));
+            outcome_filter.matches(&h.outcome) && fuzzy_matches_any(query, &[&h.folder_name, &source])
+        })
+        .collect()
+}
+
+/// Remove the items at `indices` from `items`, keeping the rest in order
+fn remove_indices<T>(items: &mut Vec<T>, indices: &[usize]) {
+    let indices: HashSet<usize> = indices.iter().copied().collect();
+    let mut i = 0;
+    items.retain(|_| {
+        i += 1;
+        !indices.contains(&(i - 1))
+    });
+}
+
+/// Whether `query` fuzzy-matches any of `candidates`. An empty query matches everything.
+fn fuzzy_matches_any(query: &str, candidates: &[&str]) -> bool {
+    use fuzzy_matcher::FuzzyMatcher;
+    use fuzzy_matcher::skim::SkimMatcherV2;
+
+    if query.is_empty() {
+        return true;
+    }
+    let matcher = SkimMatcherV2::default();
+    candidates.iter().any(|c| matcher.fuzzy_match(c, query).is_some())
+}
+
+/// `dest` as an absolute path, so a history entry still points at the same
+/// folder when downloaded again from a session started somewhere else
+fn absolute_local_dest(dest: &str) -> String {
+    match std::path::absolute(dest) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => dest.to_string(),
+    }
+}
+
+/// Where download history is kept: `$XDG_DATA_HOME/lakach/history.tsv`, falling
+/// back to `~/.local/share/lakach/history.tsv`
+fn history_file_path() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))?;
+    Some(data_home.join("lakach").join("history.tsv"))
+}
+
+/// Read the history file, oldest entry first. A missing file is an empty
+/// history and lines that don't parse are skipped.
+fn load_history(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(contents.lines().filter_map(HistoryEntry::from_line).collect())
+}
+
+fn append_history(path: &Path, entry: &HistoryEntry) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry.to_line())
+}
+
+/// Replace the history file with `entries`, going through a temporary file so
+/// a crash can't leave it half written
+fn write_history(path: &Path, entries: &[HistoryEntry]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&entry.to_line());
+        contents.push('\n');
+    }
+    let tmp = path.with_extension("tsv.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Where bookmarks are kept: `$XDG_CONFIG_HOME/lakach/bookmarks.tsv`, falling
+/// back to `~/.config` as the XDG spec says
+fn bookmarks_file_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("lakach").join("bookmarks.tsv"))
+}
+
+/// Read the bookmarks file. A missing file means no bookmarks and lines that
+/// don't parse are skipped.
+fn load_bookmarks(path: &Path) -> io::Result<Vec<Bookmark>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(contents.lines().filter_map(Bookmark::from_line).collect())
+}
+
+/// Replace the bookmarks file with `bookmarks`, through a temporary file like
+/// `write_history`
+fn write_bookmarks(path: &Path, bookmarks: &[Bookmark]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = String::new();
+    for bookmark in bookmarks {
+        contents.push_str(&bookmark.to_line());
+        contents.push('\n');
+    }
+    let tmp = path.with_extension("tsv.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl ExportFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsonl" | "json" => Some(ExportFormat::JsonLines),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Format for an output file, by extension; JSON Lines unless it ends in `.csv`
+    fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::JsonLines,
+        }
+    }
+}
+
+/// Write history entries as JSON Lines or CSV (with a header row). Times are
+/// RFC 3339 in UTC, sizes in bytes and paths lossily converted to UTF-8.
+fn export_history<'a>(
+    entries: impl IntoIterator<Item = &'a HistoryEntry>,
+    format: ExportFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    const COLUMNS: [&str; 10] = [
+        "host",
+        "remote_path",
+        "local_path",
+        "name",
+        "started_at",
+        "ended_at",
+        "duration_secs",
+        "bytes",
+        "outcome",
+        "error",
+    ];
+    if format == ExportFormat::Csv {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+
+    for entry in entries {
+        let (outcome, error) = match &entry.outcome {
+            Outcome::Completed => ("completed", None),
+            Outcome::Failed(reason) => ("failed", Some(reason.as_str())),
+            Outcome::Cancelled => ("cancelled", None),
+        };
+        let remote_path = String::from_utf8_lossy(entry.remote_path.as_os_str().as_bytes());
+        match format {
+            ExportFormat::JsonLines => {
+                let values = [
+                    json_string(&entry.remote_host),
+                    json_string(&remote_path),
+                    json_string(&entry.local_dest),
+                    json_string(&entry.folder_name),
+                    json_string(&format_rfc3339(entry.downloaded_at.saturating_sub(entry.duration))),
+                    json_string(&format_rfc3339(entry.downloaded_at)),
+                    entry.duration.to_string(),
+                    entry.bytes.map_or("null".to_string(), |b| b.to_string()),
+                    json_string(outcome),
+                    error.map_or("null".to_string(), json_string),
+                ];
+                let fields: Vec<String> = COLUMNS
+                    .iter()
+                    .zip(values)
+                    .map(|(key, value)| format!("\"{}\":{}", key, value))
+                    .collect();
+                writeln!(out, "{{{}}}", fields.join(","))?;
+            }
+            ExportFormat::Csv => {
+                let values = [
+                    csv_field(&entry.remote_host),
+                    csv_field(&remote_path),
+                    csv_field(&entry.local_dest),
+                    csv_field(&entry.folder_name),
+                    format_rfc3339(entry.downloaded_at.saturating_sub(entry.duration)),
+                    format_rfc3339(entry.downloaded_at),
+                    entry.duration.to_string(),
+                    entry.bytes.map(|b| b.to_string()).unwrap_or_default(),
+                    outcome.to_string(),
+                    csv_field(error.unwrap_or("")),
+                ];
+                writeln!(out, "{}", values.join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
 
-                        // Also read stdout to prevent blocking
-                        if let Some(stdout) = child_process.stdout.take() {
-                            let info_clone = Arc::clone(&active_info);
-                            thread::spawn(move || {
-                                let reader = BufReader::new(stdout);
-                                let mut current_file = String::new();
-
-                                for line in reader.lines().flatten() {
-                                    // Parse rsync output
-                                    let parsed = parse_rsync_line(&line, &mut current_file);
-                                    if let Some(info) = parsed {
-                                        *info_clone.lock().unwrap() = Some(info);
-                                    }
-                                }
-                            });
-                        }
+/// Quoted JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
 
-                        // Wait for completion
-                        child_process.wait().map(|status| status.success()).unwrap_or(false)
-                    } else {
-                        false
-                    };
+/// CSV field, quoted (RFC 4180) when it contains a separator, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
 
-                    // Clear active download info
-                    *active_info.lock().unwrap() = None;
+/// Escape bytes for one field of a tab separated file (history, bookmarks).
+/// Tabs, newlines and backslashes get backslash escapes and bytes that aren't
+/// UTF-8 become `\xHH`, so the file stays line based text whatever the remote
+/// names contain.
+fn escape_field(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    out
+}
 
-                    // Update status
-                    let mut downloads_lock = downloads.lock().unwrap();
-                    if let Some(d) = downloads_lock.iter_mut().find(|d| d.id == download.id) {
-                        if success {
-                            d.status = DownloadStatus::Completed;
-                            d.completed_at = Some(
-                                SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                            );
-                        } else {
-                            d.status = DownloadStatus::Failed("rsync failed".to_string());
-                            d.completed_at = Some(
-                                SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs(),
-                            );
-                        }
-                    }
-                } else {
-                    // No more queued downloads, exit thread
-                    break;
+/// Inverse of `escape_field`
+fn unescape_field(field: &str) -> Vec<u8> {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            Some(b't') => out.push(b'\t'),
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b'x') => match field.get(i + 2..i + 4).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                Some(b) => {
+                    out.push(b);
+                    i += 2;
                 }
+                None => out.push(b'x'),
+            },
+            Some(&b) => out.push(b),
+            None => {
+                out.push(b'\\');
+                break;
             }
+        }
+        i += 2;
+    }
+    out
+}
+
+/// History entries for downloads that have ended and weren't recorded yet.
+/// Completed and cancelled ones are removed from the list; failed ones stay
+/// there, marked as recorded, until retried or dismissed.
+fn record_finished(downloads: &mut Vec<Download>) -> Vec<HistoryEntry> {
+    let mut recorded = Vec::new();
+    for download in downloads.iter_mut().filter(|d| !d.recorded) {
+        let outcome = match &download.status {
+            DownloadStatus::Completed => Outcome::Completed,
+            DownloadStatus::Failed(reason) => Outcome::Failed(reason.clone()),
+            DownloadStatus::Cancelled => Outcome::Cancelled,
+            _ => continue,
+        };
+        let ended_at = download.completed_at.unwrap_or_else(unix_now);
+        download.recorded = true;
+        recorded.push(HistoryEntry {
+            folder_name: download.folder_name.clone(),
+            remote_host: download.remote_host.clone(),
+            remote_path: download.remote_path.clone(),
+            local_dest: absolute_local_dest(&download.local_dest),
+            kind: download.kind,
+            outcome,
+            bytes: download
+                .total_size
+                .or_else(|| download.progress.as_ref().map(|p| p.bytes_transferred)),
+            duration: ended_at.saturating_sub(download.started_at.unwrap_or(ended_at)),
+            downloaded_at: ended_at,
         });
     }
+    downloads.retain(|d| !matches!(d.status, DownloadStatus::Completed | DownloadStatus::Cancelled));
+    recorded
+}
 
-    fn move_completed_to_history(&mut self) {
-        let mut downloads = self.downloads.lock().unwrap();
-        let mut to_remove = Vec::new();
-
-        for (idx, download) in downloads.iter().enumerate() {
-            if let DownloadStatus::Completed = download.status {
-                if let Some(completed_at) = download.completed_at {
-                    self.history.push(HistoryEntry {
-                        folder_name: download.folder_name.clone(),
-                        remote_path: download.remote_path.clone(),
-                        downloaded_at: completed_at,
-                    });
-                    to_remove.push(idx);
-                }
+/// Cancel the download at `idx`. A queued one is dropped from the list; a
+/// running or paused one is marked Cancelled and its rsync stopped. Returns the
+/// status message, or None if there is no such download.
+fn cancel_download_at(downloads: &mut Vec<Download>, idx: usize) -> Option<String> {
+    let download = downloads.get_mut(idx)?;
+    Some(match download.status {
+        DownloadStatus::Queued => {
+            let removed = downloads.remove(idx);
+            format!("Removed from queue: {}", removed.folder_name)
+        }
+        DownloadStatus::Downloading | DownloadStatus::Paused => {
+            // The worker sees the new status and leaves it alone when rsync exits
+            download.status = DownloadStatus::Cancelled;
+            download.completed_at = Some(unix_now());
+            if let Some(child) = download.child.take() {
+                let _ = terminate(&mut child.lock().unwrap());
             }
+            format!("Cancelled: {}", download.folder_name)
         }
+        _ => "Only queued, running or paused downloads can be cancelled".to_string(),
+    })
+}
 
-        // Remove from downloads in reverse order to maintain indices
-        for idx in to_remove.iter().rev() {
-            downloads.remove(*idx);
-        }
+/// rsync for `download`, with --info=progress2 for machine-readable progress and
+/// SSH ControlMaster for connection reuse
+fn rsync_command(download: &Download, ssh_control_socket: &str) -> Command {
+    let mut ssh_cmd = OsString::from("ssh -o ControlMaster=auto -o ControlPath=");
+    ssh_cmd.push(shell_quote(OsStr::new(ssh_control_socket)));
+    ssh_cmd.push(" -o ControlPersist=600");
+    let mut command = Command::new("rsync");
+    // --protect-args sends the path over the rsync protocol instead of
+    // through the remote shell
+    command.arg("-vrtzhP").arg("--info=progress2").arg("--protect-args");
+    // Without this rsync skips a symlink given as the source ("non-regular file")
+    if let EntryKind::Symlink { .. } = download.kind {
+        command.arg("--copy-links");
+    }
+    // After a pause, pick up where the partial files (kept by -P) left off
+    if download.resume {
+        command.arg("--append-verify");
+    }
+    // The trailing slash makes rsync create a missing destination as a folder;
+    // otherwise a single file would be written under the destination's name
+    let mut local_dest = download.local_dest.clone();
+    if !local_dest.ends_with('/') {
+        local_dest.push('/');
     }
+    command
+        .arg("-e")
+        .arg(&ssh_cmd)
+        .arg("--")
+        .arg(rsync_remote_arg(&download.remote_host, &download.remote_path))
+        .arg(local_dest);
+    command
+}
 
-    fn clear_history_item(&mut self) {
-        if self.current_tab != Tab::History {
-            return;
-        }
+/// Stop `child` with SIGTERM rather than the SIGKILL of `Child::kill`, so rsync
+/// runs its signal handler and keeps the partial file it is writing. Does
+/// nothing if it has already exited.
+fn terminate(child: &mut Child) -> io::Result<()> {
+    if child.try_wait()?.is_some() {
+        return Ok(());
+    }
+    let status = Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().to_string())
+        .stderr(Stdio::null())
+        .status()?;
+    if status.success() { Ok(()) } else { child.kill() }
+}
 
-        if let Some(idx) = self.history_list_state.selected() {
-            if idx < self.history.len() {
-                let removed = self.history.remove(idx);
-                self.status_message = format!("Removed: {}", removed.folder_name);
+/// Run rsync for one download, recording progress and stderr on its entry in
+/// `downloads`. Returns once rsync has exited and its output has been read.
+fn run_rsync(
+    download: &Download,
+    ssh_control_socket: &str,
+    downloads: &Arc<Mutex<Vec<Download>>>,
+) -> io::Result<ExitStatus> {
+    let mut child = rsync_command(download, ssh_control_socket)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stderr = child.stderr.take();
+    let stdout = child.stdout.take();
 
-                // Adjust selection
-                if self.history.is_empty() {
-                    self.history_list_state.select(None);
-                } else if idx >= self.history.len() {
-                    self.history_list_state.select(Some(self.history.len() - 1));
-                }
+    // Hand the process to the download entry so it can be cancelled or paused.
+    // If that already happened while rsync was starting, stop right away.
+    let child = Arc::new(Mutex::new(child));
+    {
+        let mut downloads_lock = downloads.lock().unwrap();
+        match downloads_lock.iter_mut().find(|d| d.id == download.id) {
+            Some(d) if d.status == DownloadStatus::Downloading && !d.pause_requested => {
+                d.child = Some(Arc::clone(&child))
+            }
+            _ => {
+                let _ = terminate(&mut child.lock().unwrap());
             }
         }
     }
 
-    fn clear_all_history(&mut self) {
-        if self.current_tab != Tab::History {
-            return;
+    // Read stderr and stdout on their own threads so neither pipe fills up and
+    // blocks rsync. Progress can show up on either; stderr is also kept for the
+    // failure reason and the details popup.
+    let readers: Vec<(Box<dyn io::Read + Send>, bool)> = [
+        stderr.map(|s| (Box::new(s) as Box<dyn io::Read + Send>, true)),
+        stdout.map(|s| (Box::new(s) as Box<dyn io::Read + Send>, false)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let mut reader_threads = Vec::new();
+    for (pipe, is_stderr) in readers {
+        let downloads = Arc::clone(downloads);
+        let id = download.id;
+        reader_threads.push(thread::spawn(move || {
+            let mut current_file = String::new();
+
+            for_each_output_line(pipe, |line| {
+                let mut downloads_lock = downloads.lock().unwrap();
+                let Some(d) = downloads_lock.iter_mut().find(|d| d.id == id) else {
+                    return;
+                };
+                // Parse rsync output
+                if let Some(info) = parse_rsync_line(line, &mut current_file) {
+                    d.progress = Some(info);
+                } else if let Some(size) = parse_total_size(line) {
+                    d.total_size = Some(size);
+                } else if is_stderr {
+                    if d.stderr_tail.len() == STDERR_TAIL_LINES {
+                        d.stderr_tail.pop_front();
+                    }
+                    d.stderr_tail.push_back(line.to_string());
+                }
+            });
+        }));
+    }
+
+    // Wait for completion. Poll rather than block in wait() so the lock is free
+    // for whoever wants to kill the process.
+    let status = loop {
+        if let Some(status) = child.lock().unwrap().try_wait()? {
+            break status;
         }
+        thread::sleep(Duration::from_millis(100));
+    };
 
-        let count = self.history.len();
-        self.history.clear();
-        self.history_list_state.select(None);
-        self.status_message = format!("Cleared {} history items", count);
+    // Let the readers catch up so the stderr tail is complete
+    for handle in reader_threads {
+        let _ = handle.join();
+    }
+
+    if let Some(d) = downloads.lock().unwrap().iter_mut().find(|d| d.id == download.id) {
+        d.child = None;
+    }
+    Ok(status)
+}
+
+/// What an rsync exit code means, in plain words
+fn rsync_exit_reason(code: i32) -> &'static str {
+    match code {
+        1 => "syntax or usage error",
+        2 => "protocol incompatibility",
+        3 => "could not select files",
+        4 => "action not supported",
+        5 => "could not start rsync protocol",
+        10 => "connection error",
+        11 => "file I/O error",
+        12 => "connection closed",
+        13 => "program diagnostics error",
+        14 => "IPC error",
+        20 => "interrupted",
+        21 => "waitpid error",
+        22 => "out of memory",
+        23 => "partial transfer",
+        24 => "source files vanished",
+        25 => "max-delete limit reached",
+        30 => "timed out",
+        35 => "timed out connecting",
+        127 => "rsync not found on remote host",
+        255 => "ssh connection failed",
+        _ => "rsync failed",
+    }
+}
+
+/// Failure reason for a download. Known messages in stderr are more specific
+/// than the exit code (e.g. exit code 11 is usually a full disk), so they win.
+fn failure_reason(code: i32, stderr_tail: &VecDeque<String>) -> String {
+    const STDERR_REASONS: [(&str, &str); 9] = [
+        ("No space left on device", "disk full"),
+        ("Disk quota exceeded", "disk quota exceeded"),
+        ("Permission denied", "permission denied"),
+        ("Read-only file system", "read-only file system"),
+        ("No such file or directory", "file not found"),
+        ("Could not resolve hostname", "unknown host"),
+        ("Connection refused", "connection refused"),
+        ("Host key verification failed", "host key verification failed"),
+        ("connection unexpectedly closed", "connection closed"),
+    ];
+
+    let reason = STDERR_REASONS
+        .iter()
+        .find(|(needle, _)| stderr_tail.iter().any(|line| line.contains(needle)))
+        .map(|(_, reason)| *reason)
+        .unwrap_or_else(|| rsync_exit_reason(code));
+    format!("{} (exit code {})", reason, code)
+}
+
+/// Parse a byte count as printed by rsync: either plain with digit separators
+/// (`1,234,567`) or, with `-h`, in units of 1000 (`12.35M`)
+fn parse_rsync_size(s: &str) -> Option<u64> {
+    let s = s.replace(',', "");
+    let (number, multiplier) = match s.chars().last()? {
+        'K' => (&s[..s.len() - 1], 1e3),
+        'M' => (&s[..s.len() - 1], 1e6),
+        'G' => (&s[..s.len() - 1], 1e9),
+        'T' => (&s[..s.len() - 1], 1e12),
+        'P' => (&s[..s.len() - 1], 1e15),
+        _ => (s.as_str(), 1.0),
+    };
+    let value: f64 = number.parse().ok()?;
+    Some((value * multiplier) as u64)
+}
+
+/// Size from the `total size is 1.23G  speedup is ...` summary rsync prints
+/// at the end of a verbose transfer
+fn parse_total_size(line: &str) -> Option<u64> {
+    let rest = line.trim().strip_prefix("total size is ")?;
+    parse_rsync_size(rest.split_whitespace().next()?)
+}
+
+/// Feed each line of rsync output to `f`. Progress updates end in `\r` rather
+/// than `\n`, so both count as line endings.
+fn for_each_output_line(mut reader: impl io::Read, mut f: impl FnMut(&str)) {
+    let mut buf = [0u8; 4096];
+    let mut line = Vec::new();
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        for &b in &buf[..n] {
+            if b == b'\r' || b == b'\n' {
+                if !line.is_empty() {
+                    f(&String::from_utf8_lossy(&line));
+                    line.clear();
+                }
+            } else {
+                line.push(b);
+            }
+        }
+    }
+    if !line.is_empty() {
+        f(&String::from_utf8_lossy(&line));
     }
 }
 
@@ -653,16 +2929,26 @@ fn parse_rsync_line(line: &str, current_file: &mut String) -> Option<DownloadPro
         let parts: Vec<&str> = trimmed.split_whitespace().collect();
         let mut percentage = 0u16;
         let mut speed = String::new();
+        let mut bytes_transferred = 0u64;
+        let mut eta = String::new();
 
-        for part in parts.iter() {
+        for (i, part) in parts.iter().enumerate() {
             if part.contains("/s") {
                 speed = part.to_string();
+                // The time after the speed is the ETA (elapsed time once finished)
+                if let Some(time) = parts.get(i + 1).filter(|t| t.contains(':')) {
+                    eta = time.to_string();
+                }
             }
             if part.ends_with('%') {
                 // Parse percentage
                 if let Ok(pct) = part.trim_end_matches('%').parse::<u16>() {
                     percentage = pct.min(100);
                 }
+                // The byte count comes right before the percentage
+                if let Some(bytes) = i.checked_sub(1).and_then(|prev| parse_rsync_size(parts[prev])) {
+                    bytes_transferred = bytes;
+                }
             }
         }
 
@@ -677,6 +2963,8 @@ fn parse_rsync_line(line: &str, current_file: &mut String) -> Option<DownloadPro
                 file_name,
                 percentage,
                 speed,
+                bytes_transferred,
+                eta,
             });
         }
     }
@@ -697,21 +2985,246 @@ fn parse_rsync_line(line: &str, current_file: &mut String) -> Option<DownloadPro
     {
         // This looks like a file name - extract just the filename, not full path
         let file_path = std::path::Path::new(trimmed);
-        if let Some(file_name) = file_path.file_name() {
-            if let Some(name_str) = file_name.to_str() {
-                *current_file = name_str.to_string();
-            }
+        if let Some(file_name) = file_path.file_name()
+            && let Some(name_str) = file_name.to_str()
+        {
+            *current_file = name_str.to_string();
         }
     }
 
     None
 }
 
-fn list_remote_folders(remote_host: &str, remote_path: &str, control_socket: &str) -> io::Result<Vec<FolderInfo>> {
-    let path = if remote_path.is_empty() { "." } else { remote_path };
+/// Printable form of a remote name or path: invalid UTF-8 becomes U+FFFD and
+/// control characters such as newlines are shown escaped (`\n`)
+fn display_lossy(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    if !text.chars().any(char::is_control) {
+        return text.into_owned();
+    }
+    text.chars()
+        .map(|c| if c.is_control() { c.escape_default().to_string() } else { c.to_string() })
+        .collect()
+}
+
+/// Quote bytes for a POSIX shell. Everything inside single quotes is literal,
+/// so only embedded single quotes need care: `it's` becomes `'it'\''s'`.
+fn shell_quote(s: &OsStr) -> OsString {
+    let mut out = Vec::with_capacity(s.len() + 2);
+    out.push(b'\'');
+    for &b in s.as_bytes() {
+        if b == b'\'' {
+            out.extend_from_slice(b"'\\''");
+        } else {
+            out.push(b);
+        }
+    }
+    out.push(b'\'');
+    OsString::from_vec(out)
+}
+
+/// Remote folder typed by the user. Relative paths start from `base`, `~` is
+/// `home` (empty until it is known, which remote commands take as the home
+/// directory too) and `.` and `..` are resolved as text, so `..` stays in the
+/// path only when it leads above an empty home.
+fn resolve_remote_path(base: &Path, home: &Path, input: &str) -> PathBuf {
+    let (mut path, rest) = if input == "~" {
+        (home.to_path_buf(), "")
+    } else if let Some(rest) = input.strip_prefix("~/") {
+        (home.to_path_buf(), rest)
+    } else if let Some(rest) = input.strip_prefix('/') {
+        (PathBuf::from("/"), rest)
+    } else {
+        (base.to_path_buf(), input)
+    };
+    for component in rest.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if path.file_name().is_some() => {
+                path.pop();
+            }
+            ".." if path.has_root() && path.parent().is_none() => {} // Nothing above /
+            _ => path.push(component),
+        }
+    }
+    path
+}
+
+/// Longest common prefix of two names, ending on a character boundary
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let end = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..end]
+}
+
+/// Remote path as it should be passed to commands like `find`. An empty path
+/// means the home directory, and a relative path that starts like an option or
+/// a `find` expression (`-`, `!`, `(`) gets a `./` prefix.
+fn command_safe_path(path: &Path) -> PathBuf {
+    match path.as_os_str().as_bytes().first() {
+        None => PathBuf::from("."),
+        Some(b'-' | b'!' | b'(') => Path::new(".").join(path),
+        Some(_) => path.to_path_buf(),
+    }
+}
+
+/// `host:path` source argument for rsync. The path travels through
+/// --protect-args, so only wildcards, which the remote rsync still expands,
+/// need escaping.
+fn rsync_remote_arg(host: &str, path: &Path) -> OsString {
+    let path = command_safe_path(path);
+    let bytes = path.as_os_str().as_bytes();
+
+    let mut arg = Vec::with_capacity(host.len() + bytes.len() + 8);
+    arg.extend_from_slice(host.as_bytes());
+    arg.push(b':');
+    if bytes.iter().any(|b| matches!(b, b'*' | b'?' | b'[')) {
+        for &b in bytes {
+            if matches!(b, b'*' | b'?' | b'[' | b']' | b'\\') {
+                arg.push(b'\\');
+            }
+            arg.push(b);
+        }
+    } else {
+        arg.extend_from_slice(bytes);
+    }
+    OsString::from_vec(arg)
+}
+
+/// Sort a listing in place. Size and mtime put the largest/newest entries first;
+/// `reverse` flips whichever order the mode uses.
+fn sort_folders(folders: &mut [FolderInfo], mode: SortMode, reverse: bool) {
+    match mode {
+        SortMode::Name => folders.sort_by_key(|f| f.display.to_lowercase()),
+        SortMode::Natural => folders.sort_by(|a, b| natural_cmp(&a.display, &b.display)),
+        SortMode::Size => folders.sort_by(|a, b| {
+            b.size.unwrap_or(0).cmp(&a.size.unwrap_or(0)).then_with(|| natural_cmp(&a.display, &b.display))
+        }),
+        SortMode::Modified => folders.sort_by(|a, b| {
+            b.modified.cmp(&a.modified).then_with(|| natural_cmp(&a.display, &b.display))
+        }),
+    }
+    if reverse {
+        folders.reverse();
+    }
+}
+
+/// Case-insensitive comparison that orders runs of digits by their numeric
+/// value, so `ep2` sorts before `ep10`
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut x_num = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    x_num.push(c);
+                }
+                let mut y_num = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    y_num.push(c);
+                }
+                // Compare numerically without parsing, so arbitrarily long runs work
+                let x_trimmed = x_num.trim_start_matches('0');
+                let y_trimmed = y_num.trim_start_matches('0');
+                let ord = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+/// Human readable byte count in the same style as `rsync -h`, e.g. `1.50G`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", value, UNITS[unit])
+}
+
+/// Compact duration such as `45s`, `3m05s` or `1h02m`
+fn format_duration(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Format a unix timestamp as `YYYY-MM-DD HH:MM` (UTC)
+fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let rem = secs % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+}
+
+/// Format a unix timestamp as RFC 3339, e.g. `2024-03-01T12:34:56Z`
+fn format_rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Year, month and day (UTC) of a unix timestamp
+fn civil_date(secs: u64) -> (i64, i64, i64) {
+    let days = (secs / 86400) as i64;
+
+    // Civil date from days since epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
 
-    // List folders using SSH ControlMaster for connection reuse
-    let output = Command::new("ssh")
+/// `ssh` running `script` on `remote_host`, using SSH ControlMaster for connection reuse
+fn remote_command(remote_host: &str, control_socket: &str, script: &OsStr) -> Command {
+    let mut command = Command::new("ssh");
+    command
         .arg("-o")
         .arg("ControlMaster=auto")
         .arg("-o")
@@ -719,52 +3232,439 @@ fn list_remote_folders(remote_host: &str, remote_path: &str, control_socket: &st
         .arg("-o")
         .arg("ControlPersist=600")
         .arg(remote_host)
-        .arg(format!(
-            "find {} -maxdepth 1 -type d -not -path {}",
-            path, path
-        ))
-        .output()?;
+        .arg(script);
+    command
+}
+
+/// Script printing the absolute paths, with symlinks resolved, of the home
+/// directory and of `remote_path`. A NUL separates them, as it can't appear in
+/// either path.
+fn realpath_script(remote_path: &Path) -> OsString {
+    let mut script = OsString::from("realpath . && printf '\\0' && realpath ");
+    script.push(shell_quote(command_safe_path(remote_path).as_os_str()));
+    script
+}
+
+/// Home directory and folder printed by `realpath_script`
+fn parse_realpaths(stdout: &[u8]) -> io::Result<(PathBuf, PathBuf)> {
+    let absolute = |line: &[u8]| {
+        let path = line.strip_suffix(b"\n").unwrap_or(line);
+        if path.first() == Some(&b'/') {
+            Ok(PathBuf::from(OsStr::from_bytes(path)))
+        } else {
+            Err(io::Error::other("realpath printed no absolute path"))
+        }
+    };
+    let nul = stdout
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| io::Error::other("realpath printed no absolute path"))?;
+    Ok((absolute(&stdout[..nul])?, absolute(&stdout[nul + 1..])?))
+}
+
+/// Resolve the remote home directory and a folder, relative to it unless
+/// absolute, to absolute paths. The ssh process is kept in `child_slot` while
+/// it runs.
+fn resolve_remote_folder(
+    remote_host: &str,
+    remote_path: &Path,
+    control_socket: &str,
+    child_slot: &Mutex<Option<Child>>,
+) -> io::Result<(PathBuf, PathBuf)> {
+    let mut command = remote_command(remote_host, control_socket, &realpath_script(remote_path));
+    let output = run_killable(&mut command, child_slot)?;
 
     if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            String::from_utf8_lossy(&output.stderr),
-        ));
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr)));
     }
 
-    let folders: Vec<FolderInfo> = String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .filter_map(|line| {
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                return None;
-            }
+    parse_realpaths(&output.stdout)
+}
+
+/// List one remote folder. The ssh process is kept in `child_slot` while it
+/// runs, so another thread can kill it.
+fn list_remote_folders(
+    remote_host: &str,
+    remote_path: &Path,
+    control_socket: &str,
+    with_sizes: bool,
+    child_slot: &Mutex<Option<Child>>,
+) -> io::Result<Vec<FolderInfo>> {
+    let mut command = remote_command(remote_host, control_socket, &listing_script(remote_path, with_sizes));
+    let output = run_killable(&mut command, child_slot)?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(explain_find_error(&output.stderr)));
+    }
+
+    Ok(parse_listing(&output.stdout, remote_path))
+}
 
-            let name = std::path::Path::new(trimmed)
-                .file_name()?
-                .to_str()?
-                .to_string();
+/// Like `Command::output`, but with the child parked in `child_slot` while it runs
+fn run_killable(command: &mut Command, child_slot: &Mutex<Option<Child>>) -> io::Result<std::process::Output> {
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    *child_slot.lock().unwrap() = Some(child);
 
-            Some(FolderInfo { name })
+    // Read both pipes at once so neither can fill up and stall the other
+    let read_all = |pipe: Option<Box<dyn io::Read + Send>>| {
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut bytes);
+            }
+            bytes
         })
+    };
+    let stderr = read_all(stderr.map(|p| Box::new(p) as Box<dyn io::Read + Send>));
+    let stdout = read_all(stdout.map(|p| Box::new(p) as Box<dyn io::Read + Send>));
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    let mut child = child_slot.lock().unwrap().take().ok_or_else(|| io::Error::other("lost track of ssh"))?;
+    let status = child.wait()?;
+    Ok(std::process::Output { status, stdout, stderr })
+}
+
+/// Shell command that lists one remote folder, see `parse_listing` for the output
+fn listing_script(remote_path: &Path, with_sizes: bool) -> OsString {
+    let quoted = shell_quote(command_safe_path(remote_path).as_os_str());
+
+    // find prints one NUL terminated record per entry, so names may contain any
+    // byte but NUL. -H follows the starting point so symlinked folders can be
+    // entered; %y%Y gives the entry type and, for symlinks, the type of the
+    // target. Folder sizes need a full traversal, so `du` only runs when asked
+    // and its output follows an empty record. find lists nothing for a file
+    // without complaining, so anything but a folder is rejected up front.
+    let mut script = OsString::from("[ -d ");
+    script.push(&quoted);
+    script.push(" ] || { echo 'not a folder' >&2; exit 1; }; find -H ");
+    script.push(&quoted);
+    script.push(
+        " -mindepth 1 -maxdepth 1 \\( -type d -o -type f -o -type l \\) -printf '%y%Y\\t%s\\t%T@\\t%u\\t%m\\t%f\\0'",
+    );
+    if with_sizes {
+        script.push(" && { printf '\\0'; du -0abH -d 1 ");
+        script.push(&quoted);
+        script.push(" 2>/dev/null; true; }");
+    }
+    script
+}
+
+/// Shell command that lists everything under a remote folder down to `depth`
+/// levels, in the same record format as `listing_script` but with paths
+/// relative to the folder (`%P`) instead of bare names. Unreadable folders are
+/// skipped quietly.
+fn search_script(remote_path: &Path, depth: usize) -> OsString {
+    let mut script = OsString::from("find -H ");
+    script.push(shell_quote(command_safe_path(remote_path).as_os_str()));
+    script.push(format!(
+        " -mindepth 1 -maxdepth {} \\( -type d -o -type f -o -type l \\) -printf '%y%Y\\t%s\\t%T@\\t%u\\t%m\\t%P\\0' 2>/dev/null; true",
+        depth
+    ));
+    script
+}
+
+/// Body of the search thread: run `command`, sending batches of parsed records
+/// as they arrive. Gives up early when the receiving end is gone.
+fn run_search(
+    command: &mut Command,
+    child_slot: &Mutex<Option<Child>>,
+    sender: &mpsc::Sender<SearchEvent>,
+) -> Result<(), String> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start ssh: {}", e))?;
+    let mut stdout = child.stdout.take().ok_or("no output from ssh")?;
+    let mut stderr = child.stderr.take().ok_or("no output from ssh")?;
+    *child_slot.lock().unwrap() = Some(child);
+
+    // Drain stderr on the side so a chatty ssh can't block the search
+    let stderr_reader = thread::spawn(move || {
+        let mut text = Vec::new();
+        let _ = io::Read::read_to_end(&mut stderr, &mut text);
+        text
+    });
+
+    let mut buf = [0u8; 64 * 1024];
+    let mut pending = Vec::new();
+    let mut receiver_gone = false;
+    loop {
+        let n = match io::Read::read(&mut stdout, &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        pending.extend_from_slice(&buf[..n]);
+        // Only whole records; the rest waits for the next read
+        let Some(end) = pending.iter().rposition(|&b| b == 0) else {
+            continue;
+        };
+        let batch: Vec<FolderInfo> = pending[..end].split(|&b| b == 0).filter_map(parse_find_record).collect();
+        pending.drain(..=end);
+        if !batch.is_empty() && sender.send(SearchEvent::Found(batch)).is_err() {
+            receiver_gone = true;
+            break;
+        }
+    }
+
+    let mut child = child_slot.lock().unwrap().take().ok_or("search was not started")?;
+    if receiver_gone {
+        let _ = child.kill();
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+    match status.code() {
+        Some(0) | None => Ok(()),
+        Some(code) => {
+            let message = explain_find_error(&stderr);
+            Err(if message.is_empty() { format!("ssh exited with code {}", code) } else { message })
+        }
+    }
+}
+
+/// Error text from a remote `find`. Listings and searches rely on GNU find's
+/// `-printf`; BSD, macOS and busybox find reject it with their own wording, so
+/// say what is actually missing.
+fn explain_find_error(stderr: &[u8]) -> String {
+    let message = String::from_utf8_lossy(stderr).trim().to_string();
+    if message.contains("-printf") {
+        format!("the remote find lacks -printf, install GNU findutils there ({})", message)
+    } else {
+        message
+    }
+}
+
+fn parse_listing(stdout: &[u8], remote_path: &Path) -> Vec<FolderInfo> {
+    let path = command_safe_path(remote_path);
+    let mut records = stdout.split(|&b| b == 0);
+
+    let mut folders: Vec<FolderInfo> = records
+        .by_ref()
+        .take_while(|record| !record.is_empty())
+        .filter_map(parse_find_record)
         .collect();
 
-    Ok(folders)
+    // Remaining records are `du` output: "<bytes>\t<path>"
+    let mut dir_sizes = HashMap::new();
+    let prefix = path.as_os_str().as_bytes();
+    for record in records {
+        // Skip the listed folder itself, which du prints last
+        if let Some(tab) = record.iter().position(|&b| b == b'\t')
+            && let Some(name) = record[tab + 1..].strip_prefix(prefix).and_then(|rest| rest.strip_prefix(b"/"))
+            && let Ok(size) = String::from_utf8_lossy(&record[..tab]).parse::<u64>()
+        {
+            dir_sizes.insert(name.to_vec(), size);
+        }
+    }
+    for folder in folders.iter_mut() {
+        if folder.kind == EntryKind::Directory {
+            folder.size = dir_sizes.get(folder.name.as_bytes()).copied();
+        }
+    }
+
+    folders
 }
 
+/// Parse one `%y%Y\t%s\t%T@\t%u\t%m\t%f` record printed by `find`
+fn parse_find_record(record: &[u8]) -> Option<FolderInfo> {
+    let mut fields = record.splitn(6, |&b| b == b'\t');
+    let kind = match fields.next()? {
+        [b'd', _] => EntryKind::Directory,
+        [b'f', _] => EntryKind::File,
+        [b'l', target] => EntryKind::Symlink { to_dir: *target == b'd' },
+        _ => return None,
+    };
+    let mut text_field = || fields.next().map(String::from_utf8_lossy);
+    let size: u64 = text_field()?.parse().ok()?;
+    let modified = text_field()?.split('.').next()?.parse().ok()?;
+    let owner = text_field()?.into_owned();
+    let mode = u32::from_str_radix(&text_field()?, 8).ok()?;
+    let name = fields.next()?;
+    if name.is_empty() {
+        return None;
+    }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+    Some(FolderInfo {
+        name: OsStr::from_bytes(name).to_os_string(),
+        display: display_lossy(name),
+        kind,
+        // A directory's own st_size says nothing about its contents
+        size: if kind == EntryKind::Directory { None } else { Some(size) },
+        modified,
+        owner,
+        mode,
+    })
+}
+
+const DEFAULT_JOBS: usize = 1;
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: u64 = 5;
+const DEFAULT_SEARCH_DEPTH: usize = 8;
 
-    if args.len() < 3 {
-        eprintln!("Usage: {} <remote_source> <local_dest>", args[0]);
-        eprintln!("Example: {} user@hostname ./downloads", args[0]);
-        eprintln!("Or with path: {} user@hostname:/path/to/folder ./downloads", args[0]);
-        std::process::exit(1);
+struct Options {
+    remote_source: OsString,
+    local_dest: String,
+    jobs: usize,
+    retry_policy: RetryPolicy,
+    export_history: Option<ExportFormat>, // Print history and exit instead of starting the UI
+    search_depth: usize,
+    jail: bool, // Keep the browser inside the starting folder
+}
+
+/// Parse command line arguments (without the program name). `Err(None)` means
+/// the usage should be shown without a specific error.
+fn parse_args(args: &[OsString]) -> Result<Options, Option<String>> {
+    let mut positional = Vec::new();
+    let mut jobs = DEFAULT_JOBS;
+    let mut retry_policy = RetryPolicy {
+        max_retries: DEFAULT_RETRIES,
+        base_delay: DEFAULT_RETRY_DELAY,
+    };
+    let mut export_history = None;
+    let mut search_depth = DEFAULT_SEARCH_DEPTH;
+    let mut jail = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg_str = arg.to_string_lossy();
+        let (flag, inline_value) = match arg_str.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg_str.as_ref(), None),
+        };
+        // Value for options that take one, either `--flag=value` or `--flag value`
+        let mut value = || match &inline_value {
+            Some(value) => Ok(value.clone()),
+            None => iter
+                .next()
+                .map(|v| v.to_string_lossy().into_owned())
+                .ok_or_else(|| Some(format!("{} needs a value", flag))),
+        };
+        match flag {
+            "-h" | "--help" => return Err(None),
+            "--jail" => jail = true,
+            "-j" | "--jobs" => {
+                let value = value()?;
+                jobs = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid number of jobs: {}", value))?;
+            }
+            "--retries" => {
+                let value = value()?;
+                retry_policy.max_retries = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of retries: {}", value))?;
+            }
+            "--retry-delay" => {
+                let value = value()?;
+                retry_policy.base_delay = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid retry delay: {}", value))?;
+            }
+            "--search-depth" => {
+                let value = value()?;
+                search_depth = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid search depth: {}", value))?;
+            }
+            "--export-history" => {
+                let value = value()?;
+                export_history = Some(
+                    ExportFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown export format: {} (expected jsonl or csv)", value))?,
+                );
+            }
+            "--" => positional.extend(iter.by_ref().cloned()),
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(Some(format!("unknown option: {}", flag)));
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    // Exporting needs no remote
+    if export_history.is_some() && positional.is_empty() {
+        return Ok(Options {
+            remote_source: OsString::new(),
+            local_dest: String::new(),
+            jobs,
+            retry_policy,
+            export_history,
+            search_depth,
+            jail,
+        });
     }
 
-    let remote_source = args[1].clone();
-    let local_dest = args[2].clone();
+    let [remote_source, local_dest] = <[OsString; 2]>::try_from(positional).map_err(|_| None)?;
+    Ok(Options {
+        remote_source,
+        local_dest: local_dest.to_string_lossy().into_owned(),
+        jobs,
+        retry_policy,
+        export_history,
+        search_depth,
+        jail,
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Remote paths may be any bytes, so don't insist on UTF-8 arguments
+    let args: Vec<OsString> = env::args_os().collect();
+    let program = args[0].to_string_lossy();
+
+    let options = match parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            if let Some(e) = e {
+                eprintln!("Error: {}", e);
+            }
+            eprintln!("Usage: {} [OPTIONS] <remote_source> <local_dest>", program);
+            eprintln!("       {} --export-history <jsonl|csv>", program);
+            eprintln!("Example: {} user@hostname ./downloads", program);
+            eprintln!("Or with path: {} user@hostname:/path/to/folder ./downloads", program);
+            eprintln!();
+            eprintln!("Options:");
+            eprintln!("  -j, --jobs N           Run up to N rsync transfers at once (default {})", DEFAULT_JOBS);
+            eprintln!("      --retries N        Retry failed transfers up to N times (default {})", DEFAULT_RETRIES);
+            eprintln!(
+                "      --retry-delay SECS Wait before the first retry, doubling each time (default {})",
+                DEFAULT_RETRY_DELAY
+            );
+            eprintln!(
+                "      --search-depth N   Descend at most N levels in recursive search (default {})",
+                DEFAULT_SEARCH_DEPTH
+            );
+            eprintln!("      --jail             Don't browse above the starting folder");
+            eprintln!("      --export-history FMT Print the download history as jsonl or csv and exit");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(format) = options.export_history {
+        let history = match history_file_path() {
+            Some(path) => load_history(&path)?,
+            None => Vec::new(),
+        };
+        let mut stdout = io::stdout().lock();
+        export_history(&history, format, &mut stdout)?;
+        stdout.flush()?;
+        return Ok(());
+    }
 
     // Setup terminal
     enable_raw_mode()?;
@@ -774,10 +3674,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app
-    let mut app = App::new(remote_source, local_dest)?;
+    let mut app = App::new(
+        options.remote_source,
+        options.local_dest,
+        options.jobs,
+        options.retry_policy,
+        options.search_depth,
+        options.jail,
+    );
 
     // Run app
     let res = run_app(&mut terminal, &mut app);
+    app.kill_running_downloads();
 
     // Restore terminal
     disable_raw_mode()?;
@@ -791,8 +3699,179 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     if let Err(err) = res {
         println!("Error: {:?}", err);
     }
-
-    Ok(())
+
+    Ok(())
+}
+
+/// Row of the folder list or the search results, with the details columns when shown
+fn folder_list_item(
+    folder: &FolderInfo,
+    matches: &[usize],
+    marked: bool,
+    show_details: bool,
+    owner_width: usize,
+) -> ListItem<'static> {
+    let mut spans = vec![if marked {
+        Span::styled("+ ", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+    } else {
+        Span::raw("  ")
+    }];
+    if show_details {
+        let size = folder.size.map(format_size).unwrap_or_else(|| "-".to_string());
+        spans.push(Span::raw(format!(
+            "{}  {:<owner_width$}  {:>8}  {}  ",
+            folder.mode_string(),
+            folder.owner,
+            size,
+            format_timestamp(folder.modified),
+        )));
+    }
+    spans.extend(highlight_matches(&folder.display_name(), matches));
+    ListItem::new(Line::from(spans))
+}
+
+/// Shell-style wildcard match of a whole name: `*` matches any run of
+/// characters, `?` any one, and `[...]` one from a set, with ranges such as
+/// `[0-9]` and negation with `!` or `^`
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match glob_class(&pattern[p..], text[t]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                // No closing bracket, so it is a literal `[`
+                None => (text[t] == '[').then_some(1),
+            },
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                // Let the last `*` swallow one more character
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the `[...]` class at the start of `pattern`. Returns
+/// whether it matched and the length of the class, or None if unterminated.
+fn glob_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = pattern.get(i)?;
+        // A `]` right after the opening bracket is part of the set
+        if start == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&end| end != ']') {
+            matched |= (start..=pattern[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+}
+
+/// Spans for `text` with the characters at `matches` (char positions, as
+/// returned by `fuzzy_indices`) picked out
+fn highlight_matches(text: &str, matches: &[usize]) -> Vec<Span<'static>> {
+    let highlight = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (i, c) in text.chars().enumerate() {
+        let matched = matches.contains(&i);
+        if matched != run_matched && !run.is_empty() {
+            let style = if run_matched { highlight } else { Style::default() };
+            spans.push(Span::styled(std::mem::take(&mut run), style));
+        }
+        run_matched = matched;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, if run_matched { highlight } else { Style::default() }));
+    }
+    spans
+}
+
+/// Area of `percent_x` by `percent_y` of `area`, centered in it
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+/// Second line of a Downloads row: a text gauge followed by transfer details
+fn progress_bar_line(progress: Option<&DownloadProgress>, status: &DownloadStatus) -> Line<'static> {
+    const WIDTH: usize = 24;
+
+    let percentage = match (progress, status) {
+        (_, DownloadStatus::Completed) => 100,
+        (Some(p), _) => p.percentage,
+        (None, _) => 0,
+    };
+    let filled = WIDTH * percentage as usize / 100;
+
+    let mut spans = vec![
+        Span::raw("   "),
+        Span::styled("█".repeat(filled), Style::default().fg(Color::Cyan)),
+        Span::styled("░".repeat(WIDTH - filled), Style::default().fg(Color::DarkGray)),
+        Span::raw(format!(" {:>3}%", percentage)),
+    ];
+    if let Some(p) = progress {
+        let mut details = format!("  {}", format_size(p.bytes_transferred));
+        if *status == DownloadStatus::Downloading {
+            details.push_str(&format!("  {}", p.speed));
+            if !p.eta.is_empty() {
+                details.push_str(&format!("  ETA {}", p.eta));
+            }
+            details.push_str(&format!("  {}", p.file_name));
+        }
+        spans.push(Span::raw(details));
+    }
+    Line::from(spans)
 }
 
 fn run_app<B: ratatui::backend::Backend>(
@@ -800,8 +3879,13 @@ fn run_app<B: ratatui::backend::Backend>(
     app: &mut App,
 ) -> io::Result<()> {
     loop {
-        // Move completed downloads to history
-        app.move_completed_to_history();
+        // Move finished downloads to history
+        app.record_finished_downloads();
+        app.refresh_downloads_view();
+        app.poll_listing();
+        if let Some(search) = &mut app.search {
+            search.poll();
+        }
 
         terminal.draw(|f| {
             let chunks = Layout::default()
@@ -832,27 +3916,82 @@ fn run_app<B: ratatui::backend::Backend>(
             f.render_widget(tabs, chunks[0]);
 
             // Title/info bar
-            let title_text = match app.current_tab {
-                Tab::Browser => {
-                    let path = if app.current_path.is_empty() {
+            let title_text = match (app.current_tab, &app.search) {
+                (Tab::Browser, Some(search)) => {
+                    let root = if search.root.as_os_str().is_empty() {
                         format!("{}:~", app.remote_host)
                     } else {
-                        format!("{}:{}", app.remote_host, app.current_path)
+                        format!("{}:{}", app.remote_host, display_lossy(search.root.as_os_str().as_bytes()))
+                    };
+                    let state = if search.running {
+                        "searching...".to_string()
+                    } else if let Some(e) = &search.error {
+                        format!("failed: {}", e)
+                    } else if search.truncated {
+                        format!("stopped at {} results", MAX_SEARCH_RESULTS)
+                    } else {
+                        "done".to_string()
                     };
-                    if app.filter_query.is_empty() {
-                        path
+                    let mut title = format!(
+                        "Search {} (depth {}) | {}/{} | {}",
+                        root,
+                        search.depth,
+                        search.view.len(),
+                        search.results.len(),
+                        state
+                    );
+                    if !search.query.is_empty() {
+                        title.push_str(&format!(" | Filter: {}", search.query));
+                    }
+                    title
+                }
+                (Tab::Browser, None) => {
+                    let path = if app.current_path.as_os_str().is_empty() {
+                        format!("{}:~", app.remote_host)
                     } else {
-                        format!("{} | Filter: {}", path, app.filter_query)
+                        format!("{}:{}", app.remote_host, display_lossy(app.current_path.as_os_str().as_bytes()))
+                    };
+                    let sort = format!(
+                        "Sort: {}{}",
+                        app.sort_mode.label(),
+                        if app.sort_reverse { " (rev)" } else { "" }
+                    );
+                    let mut title = format!("{} | {}", path, sort);
+                    if !app.filter_query.is_empty() {
+                        title.push_str(&format!(" | Filter: {}", app.filter_query));
+                    }
+                    if !app.marked.is_empty() {
+                        title.push_str(&format!(" | Marked: {}", app.marked.len()));
                     }
+                    title
                 }
-                Tab::Downloads => {
+                (Tab::Downloads, _) => {
                     let downloads = app.downloads.lock().unwrap();
-                    format!("Downloading: {} | Queued: {} | Total: {}",
+                    let counts = format!("Downloading: {}/{} | Queued: {} | Paused: {} | Total: {}",
                         downloads.iter().filter(|d| d.status == DownloadStatus::Downloading).count(),
+                        app.jobs,
                         downloads.iter().filter(|d| d.status == DownloadStatus::Queued).count(),
-                        downloads.len())
+                        downloads.iter().filter(|d| d.status == DownloadStatus::Paused).count(),
+                        downloads.len());
+                    if app.downloads_filter_query.is_empty() {
+                        counts
+                    } else {
+                        format!("{} | Filter: {} ({} shown)", counts, app.downloads_filter_query, app.downloads_view.len())
+                    }
+                }
+                (Tab::History, _) => {
+                    let counts = format!(
+                        "History: {}/{} | Outcome: {}",
+                        app.history_view.len(),
+                        app.history.len(),
+                        app.outcome_filter.label()
+                    );
+                    if app.history_filter_query.is_empty() {
+                        counts
+                    } else {
+                        format!("{} | Filter: {}", counts, app.history_filter_query)
+                    }
                 }
-                Tab::History => format!("Downloaded this session: {}", app.history.len()),
             };
             let title = Paragraph::new(title_text)
                 .style(Style::default().fg(Color::Cyan))
@@ -869,16 +4008,66 @@ fn run_app<B: ratatui::backend::Backend>(
                 .split(chunks[2]);
 
             // Main content
-            match app.current_tab {
-                Tab::Browser => {
+            match (app.current_tab, &app.search) {
+                (Tab::Browser, Some(search)) => {
+                    // There can be a great many results, so only build the rows that fit
+                    let height = main_chunks[0].height.saturating_sub(2).max(1) as usize;
+                    let selected = search.list_state.selected().unwrap_or(0);
+                    let first = selected.saturating_sub(height - 1);
+                    let shown = &search.view[first.min(search.view.len())..(first + height).min(search.view.len())];
+                    let owner_width = shown
+                        .iter()
+                        .map(|(_, i, _)| search.results[*i].owner.chars().count())
+                        .max()
+                        .unwrap_or(0);
+                    let items: Vec<ListItem> = shown
+                        .iter()
+                        .map(|(_, i, matches)| {
+                            folder_list_item(&search.results[*i], matches, false, app.show_details, owner_width)
+                        })
+                        .collect();
+
+                    let list = List::new(items)
+                        .block(Block::default().borders(Borders::ALL).title("Search results"))
+                        .highlight_style(
+                            Style::default()
+                                .bg(Color::DarkGray)
+                                .add_modifier(Modifier::BOLD),
+                        )
+                        .highlight_symbol(">> ");
+
+                    let mut window_state = ListState::default();
+                    window_state.select(search.list_state.selected().map(|i| i - first));
+                    f.render_stateful_widget(list, main_chunks[0], &mut window_state);
+                }
+                (Tab::Browser, None) => {
+                    let owner_width = app.folders.iter().map(|f| f.owner.chars().count()).max().unwrap_or(0);
                     let items: Vec<ListItem> = app
                         .folders
                         .iter()
-                        .map(|folder| ListItem::new(folder.name.as_str()))
+                        .zip(&app.folder_matches)
+                        .map(|(folder, matches)| {
+                            let marked = app.marked.contains(&folder.name);
+                            folder_list_item(folder, matches, marked, app.show_details, owner_width)
+                        })
                         .collect();
 
+                    let block_title = match &app.listing {
+                        Some(pending) => Line::styled(
+                            format!(
+                                "Files - Loading {}... (Esc: cancel)",
+                                if pending.path.as_os_str().is_empty() {
+                                    "~".to_string()
+                                } else {
+                                    display_lossy(pending.path.as_os_str().as_bytes())
+                                }
+                            ),
+                            Style::default().fg(Color::Yellow),
+                        ),
+                        None => Line::from("Files"),
+                    };
                     let list = List::new(items)
-                        .block(Block::default().borders(Borders::ALL).title("Folders"))
+                        .block(Block::default().borders(Borders::ALL).title(block_title))
                         .highlight_style(
                             Style::default()
                                 .bg(Color::DarkGray)
@@ -888,24 +4077,46 @@ fn run_app<B: ratatui::backend::Backend>(
 
                     f.render_stateful_widget(list, main_chunks[0], &mut app.browser_list_state);
                 }
-                Tab::Downloads => {
+                (Tab::Downloads, _) => {
                     let downloads = app.downloads.lock().unwrap();
-                    let items: Vec<ListItem> = downloads
+                    let now = unix_now();
+                    let items: Vec<ListItem> = app
+                        .downloads_view
                         .iter()
+                        .filter_map(|id| downloads.iter().find(|d| d.id == *id))
                         .map(|d| {
                             let status_str = match &d.status {
+                                DownloadStatus::Queued if d.attempt > 0 => match d.retry_at {
+                                    Some(t) if t > now => format!(
+                                        "Retry {}/{} in {}s",
+                                        d.attempt,
+                                        app.retry_policy.max_retries,
+                                        t - now
+                                    ),
+                                    _ => format!("Retry {}/{} queued", d.attempt, app.retry_policy.max_retries),
+                                },
                                 DownloadStatus::Queued => "Queued".to_string(),
+                                DownloadStatus::Downloading if d.pause_requested => "Pausing...".to_string(),
                                 DownloadStatus::Downloading => "Downloading...".to_string(),
+                                DownloadStatus::Paused => "Paused".to_string(),
                                 DownloadStatus::Completed => "Completed".to_string(),
                                 DownloadStatus::Failed(e) => format!("Failed: {}", e),
+                                DownloadStatus::Cancelled => "Cancelled".to_string(),
                             };
                             let style = match &d.status {
                                 DownloadStatus::Queued => Style::default().fg(Color::Yellow),
                                 DownloadStatus::Downloading => Style::default().fg(Color::Cyan),
+                                DownloadStatus::Paused => Style::default().fg(Color::Magenta),
                                 DownloadStatus::Completed => Style::default().fg(Color::Green),
                                 DownloadStatus::Failed(_) => Style::default().fg(Color::Red),
+                                DownloadStatus::Cancelled => Style::default().fg(Color::DarkGray),
                             };
-                            ListItem::new(format!("{} - {}", d.folder_name, status_str)).style(style)
+                            let progress_line = progress_bar_line(d.progress.as_ref(), &d.status);
+                            ListItem::new(vec![
+                                Line::from(format!("{} - {}", d.folder_name, status_str)),
+                                progress_line,
+                            ])
+                            .style(style)
                         })
                         .collect();
 
@@ -920,16 +4131,14 @@ fn run_app<B: ratatui::backend::Backend>(
 
                     f.render_stateful_widget(list, main_chunks[0], &mut app.downloads_list_state);
                 }
-                Tab::History => {
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
+                (Tab::History, _) => {
+                    let now = unix_now();
 
                     let items: Vec<ListItem> = app
-                        .history
+                        .history_view
                         .iter()
-                        .map(|h| {
+                        .map(|&i| {
+                            let h = &app.history[i];
                             let elapsed = now.saturating_sub(h.downloaded_at);
                             let time_str = if elapsed < 60 {
                                 format!("{}s ago", elapsed)
@@ -940,7 +4149,34 @@ fn run_app<B: ratatui::backend::Backend>(
                             } else {
                                 format!("{}d ago", elapsed / 86400)
                             };
-                            ListItem::new(format!("{} ({}) - {}", h.folder_name, h.remote_path, time_str))
+                            let (result, style) = match &h.outcome {
+                                Outcome::Completed => (
+                                    format!(
+                                        "{} in {}",
+                                        h.bytes.map(format_size).unwrap_or_else(|| "?".to_string()),
+                                        format_duration(h.duration)
+                                    ),
+                                    Style::default(),
+                                ),
+                                Outcome::Failed(reason) => (
+                                    format!("failed after {}: {}", format_duration(h.duration), reason),
+                                    Style::default().fg(Color::Red),
+                                ),
+                                Outcome::Cancelled => (
+                                    format!("cancelled after {}", format_duration(h.duration)),
+                                    Style::default().fg(Color::DarkGray),
+                                ),
+                            };
+                            ListItem::new(format!(
+                                "{} ({}:{} -> {}) - {} - {}",
+                                h.folder_name,
+                                h.remote_host,
+                                display_lossy(h.remote_path.as_os_str().as_bytes()),
+                                h.local_dest,
+                                result,
+                                time_str
+                            ))
+                            .style(style)
                         })
                         .collect();
 
@@ -959,14 +4195,40 @@ fn run_app<B: ratatui::backend::Backend>(
 
             // Legend panel
             let legend_items = match app.current_tab {
+                Tab::Browser if app.search.is_some() => vec![
+                    "j/k: Navigate",
+                    "↑/↓: Navigate",
+                    "PgUp/Dn: Page",
+                    "Enter: Go to folder",
+                    "/: Narrow",
+                    "d: Download",
+                    "c: Stop search",
+                    "Esc: Close search",
+                    "Tab: Switch tab",
+                    "q: Quit",
+                ],
                 Tab::Browser => vec![
                     "j/k: Navigate",
                     "↑/↓: Navigate",
                     "PgUp/Dn: Page",
                     "Enter: Open",
                     "Bksp: Back",
+                    "H/L: History back/fwd",
+                    "g/:: Go to path",
+                    "b: Bookmark folder",
+                    "B: Bookmarks",
                     "/: Filter",
+                    "F: Search subtree",
+                    "r/F5: Refresh",
+                    "i: Details",
+                    "u: Folder sizes",
+                    "s: Sort mode",
+                    "S: Reverse sort",
                     "d: Download",
+                    "Space: Mark",
+                    "a/A: Mark all/invert",
+                    "*: Mark pattern",
+                    "D: Download marked",
                     "T: Change dest",
                     "Tab: Switch tab",
                     "q: Quit",
@@ -975,6 +4237,12 @@ fn run_app<B: ratatui::backend::Backend>(
                     "j/k: Navigate",
                     "↑/↓: Navigate",
                     "PgUp/Dn: Page",
+                    "c: Cancel",
+                    "p: Pause/resume",
+                    "r: Retry",
+                    "x: Dismiss failed",
+                    "/: Filter",
+                    "Enter: Details",
                     "Tab: Switch tab",
                     "q: Quit",
                 ],
@@ -982,6 +4250,11 @@ fn run_app<B: ratatui::backend::Backend>(
                     "j/k: Navigate",
                     "↑/↓: Navigate",
                     "PgUp/Dn: Page",
+                    "d: Download again",
+                    "D: Download to...",
+                    "o: Filter outcome",
+                    "/: Filter",
+                    "E: Export",
                     "x: Clear item",
                     "X: Clear all",
                     "Tab: Switch tab",
@@ -1012,11 +4285,30 @@ fn run_app<B: ratatui::backend::Backend>(
                         .block(Block::default().borders(Borders::ALL).title("Last task"));
                     f.render_widget(status, status_chunks[0]);
 
-                    // Active download section with file name and progress gauge
-                    let download_info = app.active_download_info.lock().unwrap();
-                    if let Some(ref progress) = *download_info {
+                    // Active download section with file name and progress gauge. Shows the
+                    // selected download if it is running, otherwise the first running one.
+                    let downloads = app.downloads.lock().unwrap();
+                    let selected = app
+                        .downloads_list_state
+                        .selected()
+                        .and_then(|i| app.downloads_view.get(i))
+                        .and_then(|id| downloads.iter().find(|d| d.id == *id));
+                    let active: Vec<&Download> = downloads
+                        .iter()
+                        .filter(|d| d.status == DownloadStatus::Downloading && d.progress.is_some())
+                        .collect();
+                    let shown = selected
+                        .filter(|d| d.status == DownloadStatus::Downloading)
+                        .and_then(|d| d.progress.as_ref())
+                        .or_else(|| active.first().and_then(|d| d.progress.as_ref()));
+                    if let Some(progress) = shown {
                         // Create block first
-                        let block = Block::default().borders(Borders::ALL).title("Active Download");
+                        let title = if active.len() > 1 {
+                            format!("Active Downloads ({})", active.len())
+                        } else {
+                            "Active Download".to_string()
+                        };
+                        let block = Block::default().borders(Borders::ALL).title(title);
                         let inner = block.inner(status_chunks[1]);
                         f.render_widget(block, status_chunks[1]);
 
@@ -1035,7 +4327,11 @@ fn run_app<B: ratatui::backend::Backend>(
                         f.render_widget(file_paragraph, download_chunks[0]);
 
                         // Progress gauge below
-                        let gauge_label = format!("{}% @ {}", progress.percentage, progress.speed);
+                        let gauge_label = if progress.eta.is_empty() {
+                            format!("{}% @ {}", progress.percentage, progress.speed)
+                        } else {
+                            format!("{}% @ {} ETA {}", progress.percentage, progress.speed, progress.eta)
+                        };
                         let gauge = Gauge::default()
                             .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black))
                             .percent(progress.percentage)
@@ -1054,6 +4350,36 @@ fn run_app<B: ratatui::backend::Backend>(
                         .block(Block::default().borders(Borders::ALL).title("Download Destination (Enter: save, Esc: cancel)"));
                     f.render_widget(input, chunks[3]);
                 }
+                InputMode::EditingRequeueDest => {
+                    let input = Paragraph::new(app.input_buffer.as_str())
+                        .style(Style::default().fg(Color::White))
+                        .block(Block::default().borders(Borders::ALL).title("Download Again To (Enter: queue, Esc: cancel)"));
+                    f.render_widget(input, chunks[3]);
+                }
+                InputMode::MarkingGlob => {
+                    let input = Paragraph::new(app.input_buffer.as_str())
+                        .style(Style::default().fg(Color::White))
+                        .block(Block::default().borders(Borders::ALL).title("Mark Matching (*, ?, [abc]; Enter: mark, Esc: cancel)"));
+                    f.render_widget(input, chunks[3]);
+                }
+                InputMode::JumpingToPath => {
+                    let input = Paragraph::new(app.input_buffer.as_str())
+                        .style(Style::default().fg(Color::White))
+                        .block(Block::default().borders(Borders::ALL).title("Go To (Tab: complete, Enter: go, Esc: cancel)"));
+                    f.render_widget(input, chunks[3]);
+                }
+                InputMode::PickingBookmark => {
+                    let input = Paragraph::new(app.input_buffer.as_str())
+                        .style(Style::default().fg(Color::White))
+                        .block(Block::default().borders(Borders::ALL).title("Bookmark Filter (Enter: go, Del: remove, Esc: cancel)"));
+                    f.render_widget(input, chunks[3]);
+                }
+                InputMode::ExportingHistory => {
+                    let input = Paragraph::new(app.input_buffer.as_str())
+                        .style(Style::default().fg(Color::White))
+                        .block(Block::default().borders(Borders::ALL).title("Export History To (Enter: export, Esc: cancel)"));
+                    f.render_widget(input, chunks[3]);
+                }
                 InputMode::Filtering => {
                     let input = Paragraph::new(app.input_buffer.as_str())
                         .style(Style::default().fg(Color::White))
@@ -1061,54 +4387,1032 @@ fn run_app<B: ratatui::backend::Backend>(
                     f.render_widget(input, chunks[3]);
                 }
             }
+
+            // Details popup for the selected download
+            if app.show_download_details && app.current_tab == Tab::Downloads {
+                let downloads = app.downloads.lock().unwrap();
+                if let Some(d) = app
+                    .downloads_list_state
+                    .selected()
+                    .and_then(|i| app.downloads_view.get(i))
+                    .and_then(|id| downloads.iter().find(|d| d.id == *id))
+                {
+                    let status = match &d.status {
+                        DownloadStatus::Queued => "Queued".to_string(),
+                        DownloadStatus::Downloading => "Downloading".to_string(),
+                        DownloadStatus::Paused => "Paused".to_string(),
+                        DownloadStatus::Completed => "Completed".to_string(),
+                        DownloadStatus::Failed(reason) => format!("Failed: {}", reason),
+                        DownloadStatus::Cancelled => "Cancelled".to_string(),
+                    };
+                    let mut lines = vec![
+                        Line::from(format!(
+                            "Source:      {}:{}",
+                            d.remote_host,
+                            display_lossy(d.remote_path.as_os_str().as_bytes())
+                        )),
+                        Line::from(format!("Destination: {}", d.local_dest)),
+                        Line::from(format!("Status:      {}", status)),
+                        Line::from(format!("Retries:     {}/{}", d.attempt, app.retry_policy.max_retries)),
+                        Line::from(""),
+                        Line::styled("rsync stderr:", Style::default().add_modifier(Modifier::BOLD)),
+                    ];
+                    if d.stderr_tail.is_empty() {
+                        lines.push(Line::styled("(nothing)", Style::default().fg(Color::DarkGray)));
+                    } else {
+                        lines.extend(
+                            d.stderr_tail
+                                .iter()
+                                .map(|line| Line::styled(line.clone(), Style::default().fg(Color::Red))),
+                        );
+                    }
+
+                    let area = centered_rect(80, 70, f.area());
+                    let popup = Paragraph::new(lines)
+                        .wrap(Wrap { trim: false })
+                        .block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title(format!("{} (Enter/Esc: close)", d.folder_name)),
+                        );
+                    f.render_widget(Clear, area);
+                    f.render_widget(popup, area);
+                }
+            }
+
+            // Bookmark picker
+            if app.input_mode == InputMode::PickingBookmark {
+                let items: Vec<ListItem> = app
+                    .bookmark_view
+                    .iter()
+                    .map(|(i, matches)| ListItem::new(Line::from(highlight_matches(&app.bookmarks[*i].label(), matches))))
+                    .collect();
+                let area = centered_rect(70, 60, f.area());
+                let list = List::new(items)
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(format!("Bookmarks {}/{}", app.bookmark_view.len(), app.bookmarks.len())),
+                    )
+                    .highlight_style(
+                        Style::default()
+                            .bg(Color::DarkGray)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                    .highlight_symbol(">> ");
+                f.render_widget(Clear, area);
+                f.render_stateful_widget(list, area, &mut app.bookmark_list_state);
+            }
         })?;
 
-        if event::poll(std::time::Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                match app.input_mode {
-                    InputMode::Normal => {
-                        match key.code {
-                            KeyCode::Char('q') => return Ok(()),
-                            KeyCode::Char('T') => app.start_editing_path(),
-                            KeyCode::Tab => app.next_tab(),
-                            KeyCode::BackTab => app.prev_tab(),
-                            KeyCode::Char('/') => app.start_filtering(),
-                            KeyCode::Char('d') => app.queue_download(),
-                            KeyCode::Char('x') => app.clear_history_item(),
-                            KeyCode::Char('X') => app.clear_all_history(),
-                            KeyCode::Enter => {
-                                app.enter_folder()?;
-                            }
-                            KeyCode::Backspace => {
-                                app.go_back()?;
+        if event::poll(std::time::Duration::from_millis(50))?
+            && let Event::Key(key) = event::read()?
+        {
+            match app.input_mode {
+                InputMode::Normal => {
+                    match key.code {
+                        KeyCode::Char('q') => return Ok(()),
+                        KeyCode::Char('T') => app.start_editing_path(),
+                        KeyCode::Tab => app.next_tab(),
+                        KeyCode::BackTab => app.prev_tab(),
+                        KeyCode::Char('/') => app.start_filtering(),
+                        KeyCode::Char('d') => {
+                            app.queue_download();
+                            app.requeue_history_item(None);
+                        }
+                        KeyCode::Char('D') => {
+                            app.queue_marked();
+                            app.start_requeue_with_destination();
+                        }
+                        KeyCode::Char(' ') => app.toggle_mark(),
+                        KeyCode::Char('a') => app.mark_all(),
+                        KeyCode::Char('A') => app.invert_marks(),
+                        KeyCode::Char('*') => app.start_mark_glob(),
+                        KeyCode::Char('g') | KeyCode::Char(':') => app.start_jump_to_path(),
+                        KeyCode::Char('b') => app.toggle_bookmark(),
+                        KeyCode::Char('H') => app.step_nav_history(true),
+                        KeyCode::Char('L') => app.step_nav_history(false),
+                        KeyCode::Left if key.modifiers.contains(KeyModifiers::ALT) => app.step_nav_history(true),
+                        KeyCode::Right if key.modifiers.contains(KeyModifiers::ALT) => app.step_nav_history(false),
+                        KeyCode::Char('B') => app.open_bookmarks(),
+                        KeyCode::Char('i') => app.toggle_details(),
+                        KeyCode::Char('u') => app.toggle_dir_sizes(),
+                        KeyCode::Char('s') => app.cycle_sort_mode(),
+                        KeyCode::Char('S') => app.toggle_sort_reverse(),
+                        KeyCode::Char('c') => {
+                            app.cancel_download();
+                            app.stop_search();
+                        }
+                        KeyCode::Char('F') => app.start_search(),
+                        KeyCode::Char('p') => app.toggle_pause_download(),
+                        KeyCode::Char('r') => {
+                            app.retry_download();
+                            app.refresh_listing();
+                        }
+                        KeyCode::F(5) => app.refresh_listing(),
+                        KeyCode::Char('o') => app.cycle_outcome_filter(),
+                        KeyCode::Char('E') => app.start_exporting_history(),
+                        KeyCode::Char('x') => {
+                            app.dismiss_download();
+                            app.clear_history_item();
+                        }
+                        KeyCode::Char('X') => app.clear_all_history(),
+                        KeyCode::Enter => {
+                            app.enter_folder();
+                            app.toggle_download_details();
+                        }
+                        KeyCode::Esc => {
+                            app.show_download_details = false;
+                            if !app.cancel_listing() {
+                                app.close_search();
                             }
-                            KeyCode::Down | KeyCode::Char('j') => app.next(),
-                            KeyCode::Up | KeyCode::Char('k') => app.previous(),
-                            KeyCode::PageUp => app.page_up(),
-                            KeyCode::PageDown => app.page_down(),
-                            _ => {}
                         }
+                        KeyCode::Backspace => app.go_back(),
+                        KeyCode::Down | KeyCode::Char('j') => app.next(),
+                        KeyCode::Up | KeyCode::Char('k') => app.previous(),
+                        KeyCode::PageUp => app.page_up(),
+                        KeyCode::PageDown => app.page_down(),
+                        _ => {}
                     }
-                    InputMode::EditingPath => {
-                        match key.code {
-                            KeyCode::Enter => app.confirm_path_change(),
-                            KeyCode::Esc => app.cancel_input(),
-                            KeyCode::Backspace => app.handle_input_backspace(),
-                            KeyCode::Char(c) => app.handle_input_char(c),
-                            _ => {}
-                        }
+                }
+                InputMode::EditingPath => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_path_change(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
                     }
-                    InputMode::Filtering => {
-                        match key.code {
-                            KeyCode::Enter => app.confirm_filter(),
-                            KeyCode::Esc => app.cancel_filter(),
-                            KeyCode::Backspace => app.handle_input_backspace(),
-                            KeyCode::Char(c) => app.handle_input_char(c),
-                            _ => {}
-                        }
+                }
+                InputMode::EditingRequeueDest => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_requeue_destination(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::MarkingGlob => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_mark_glob(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::JumpingToPath => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_jump_to_path(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Tab => app.complete_jump_path(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::PickingBookmark => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_bookmark(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Delete => app.remove_selected_bookmark(),
+                        KeyCode::Down => app.next(),
+                        KeyCode::Up => app.previous(),
+                        KeyCode::PageUp => app.page_up(),
+                        KeyCode::PageDown => app.page_down(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::ExportingHistory => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_export_history(),
+                        KeyCode::Esc => app.cancel_input(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
+                    }
+                }
+                InputMode::Filtering => {
+                    match key.code {
+                        KeyCode::Enter => app.confirm_filter(),
+                        KeyCode::Esc => app.cancel_filter(),
+                        KeyCode::Backspace => app.handle_input_backspace(),
+                        KeyCode::Char(c) => app.handle_input_char(c),
+                        _ => {}
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE_NAMES: &[&str] = &[
+        "with space",
+        "it's",
+        "'",
+        "\"double\"",
+        "$HOME",
+        "$(touch pwned)",
+        "`id`",
+        "a; rm -rf ~",
+        "a && b || c",
+        "semi;colon",
+        "back\\slash",
+        "new\nline",
+        "trailing\n",
+        "tab\there",
+        "glob*?[x]",
+        "~",
+        "{a,b}",
+        ">redirect",
+        "#comment",
+    ];
+
+    /// Not valid UTF-8, but a perfectly good file name
+    const NON_UTF8_NAME: &[u8] = b"caf\xe9 \xff";
+
+    /// Round-trip a quoted string through a real shell and return what it saw
+    fn echo_through_shell(quoted: &OsStr) -> Vec<u8> {
+        let mut script = OsString::from("printf '%s' ");
+        script.push(quoted);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .output()
+            .expect("failed to run sh");
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    #[test]
+    fn shell_quote_round_trips_hostile_names() {
+        for name in HOSTILE_NAMES {
+            let quoted = shell_quote(OsStr::new(name));
+            assert_eq!(echo_through_shell(&quoted), name.as_bytes(), "name: {:?}", name);
+        }
+        let quoted = shell_quote(OsStr::from_bytes(NON_UTF8_NAME));
+        assert_eq!(echo_through_shell(&quoted), NON_UTF8_NAME);
+    }
+
+    #[test]
+    fn shell_quote_survives_concatenation() {
+        // Folder names end up joined with their parent path
+        let path = Path::new(HOSTILE_NAMES[1]).join(HOSTILE_NAMES[6]);
+        assert_eq!(echo_through_shell(&shell_quote(path.as_os_str())), path.as_os_str().as_bytes());
+    }
+
+    #[test]
+    fn listing_handles_hostile_names() {
+        let dir = env::temp_dir().join(format!("lakach-test-listing-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut names: Vec<OsString> = HOSTILE_NAMES.iter().map(OsString::from).collect();
+        names.push(OsStr::from_bytes(NON_UTF8_NAME).to_os_string());
+        for name in &names {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join("file"), b"12345").unwrap();
+        }
+
+        let output = Command::new("sh").arg("-c").arg(listing_script(&dir, true)).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let mut listed: Vec<FolderInfo> = parse_listing(&output.stdout, &dir);
+        listed.sort_by(|a, b| a.name.cmp(&b.name));
+        names.sort();
+        assert_eq!(listed.iter().map(|f| f.name.clone()).collect::<Vec<_>>(), names);
+        assert!(listed.iter().all(|f| f.kind == EntryKind::Directory && f.size.is_some()));
+
+        let non_utf8 = listed.iter().find(|f| f.name.as_bytes() == NON_UTF8_NAME).unwrap();
+        assert_eq!(non_utf8.display, "caf\u{fffd} \u{fffd}");
+        let newline = listed.iter().find(|f| f.name == "new\nline").unwrap();
+        assert_eq!(newline.display, "new\\nline");
+    }
+
+    #[test]
+    fn listing_rejects_anything_but_folders() {
+        let dir = env::temp_dir().join(format!("lakach-test-not-folder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("-file"), b"12345").unwrap();
+        std::os::unix::fs::symlink("-file", dir.join("link")).unwrap();
+
+        let list = |path: &Path| Command::new("sh").arg("-c").arg(listing_script(path, false)).output().unwrap();
+        for path in [dir.join("-file"), dir.join("link"), dir.join("missing")] {
+            let output = list(&path);
+            assert!(!output.status.success(), "{:?} was listed", path);
+            assert_eq!(String::from_utf8_lossy(&output.stderr).trim(), "not a folder");
+        }
+        let output = list(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(parse_listing(&output.stdout, &dir).len(), 2);
+    }
+
+    #[test]
+    fn explain_find_error_points_at_gnu_findutils() {
+        let bsd = explain_find_error(b"find: -printf: unknown primary or operator\n");
+        assert!(bsd.starts_with("the remote find lacks -printf"), "{}", bsd);
+        assert!(bsd.ends_with("(find: -printf: unknown primary or operator)"));
+        assert!(explain_find_error(b"find: unrecognized: -printf").contains("GNU findutils"));
+        assert_eq!(explain_find_error(b"not a folder\n"), "not a folder");
+        assert_eq!(explain_find_error(b""), "");
+    }
+
+    #[test]
+    fn realpath_resolves_symlinks_and_hostile_names() {
+        let dir = env::temp_dir().join(format!("lakach-test-realpath-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let target = dir.join(HOSTILE_NAMES[5]).join("new\nline");
+        std::fs::create_dir_all(&target).unwrap();
+        std::os::unix::fs::symlink(&target, dir.join("-link")).unwrap();
+        let expected = (dir.canonicalize().unwrap(), target.canonicalize().unwrap());
+
+        // Relative paths start where the command runs, like the remote home directory
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(realpath_script(Path::new("-link")))
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(parse_realpaths(&output.stdout).unwrap(), expected);
+
+        assert!(parse_realpaths(b"/home/u\n\0relative\n").is_err());
+        assert!(parse_realpaths(b"/home/u\n").is_err());
+        assert_eq!(parse_realpaths(b"/h\n\0/\n").unwrap(), (PathBuf::from("/h"), PathBuf::from("/")));
+    }
+
+    #[test]
+    fn parse_find_record_reads_fields() {
+        let file = parse_find_record(b"ff\t1234\t1700000000.5\talice\t644\tep1.mkv").unwrap();
+        assert_eq!(file.name, "ep1.mkv");
+        assert!(file.kind == EntryKind::File);
+        assert_eq!((file.size, file.modified, file.owner.as_str(), file.mode), (Some(1234), 1700000000, "alice", 0o644));
+
+        // A directory's own st_size isn't its contents
+        let dir = parse_find_record(b"dd\t4096\t1700000000\tbob\t755\tSeason 1").unwrap();
+        assert!(dir.kind == EntryKind::Directory && dir.size.is_none());
+
+        let link = parse_find_record(b"ld\t7\t1700000000\tbob\t777\tlatest").unwrap();
+        assert!(link.kind == EntryKind::Symlink { to_dir: true } && link.is_dir());
+
+        // The name is the last field, so it may hold tabs
+        let tabbed = parse_find_record(b"ff\t1\t1\tu\t600\ta\tb").unwrap();
+        assert_eq!(tabbed.name, "a\tb");
+
+        assert!(parse_find_record(b"pp\t0\t0\tu\t600\tfifo").is_none());
+        assert!(parse_find_record(b"ff\tbig\t0\tu\t600\tx").is_none());
+        assert!(parse_find_record(b"ff\t1\t1\tu\t600\t").is_none());
+        assert!(parse_find_record(b"ff\t1\t1").is_none());
+    }
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+
+        // Numeric runs
+        assert_eq!(natural_cmp("ep2", "ep10"), Less);
+        assert_eq!(natural_cmp("ep10", "ep9"), Greater);
+        assert_eq!(natural_cmp("s1e10", "s1e2"), Greater);
+        assert_eq!(natural_cmp("x99999999999999999999", "x100000000000000000000"), Less);
+
+        // Leading zeros: same value, then the plain comparison breaks the tie
+        assert_eq!(natural_cmp("ep02", "ep10"), Less);
+        assert_eq!(natural_cmp("ep01", "ep1"), Less);
+        assert_eq!(natural_cmp("ep007", "ep7"), Less);
+
+        // Mixed case compares case-insensitively, with case only breaking ties
+        assert_eq!(natural_cmp("Ep2", "ep10"), Less);
+        assert_eq!(natural_cmp("apple", "Banana"), Less);
+        assert_eq!(natural_cmp("EP1", "ep1"), Less);
+
+        // Equal prefixes: the shorter name first
+        assert_eq!(natural_cmp("ep1", "ep1a"), Less);
+        assert_eq!(natural_cmp("ep", "ep1"), Less);
+        assert_eq!(natural_cmp("same", "same"), Equal);
+
+        let mut names = vec!["ep10", "Ep1", "ep2", "ep02", "ep1"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["Ep1", "ep1", "ep02", "ep2", "ep10"]);
+    }
+
+    #[test]
+    fn format_timestamp_gives_utc_minutes() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_timestamp(1_709_296_496), "2024-03-01 12:34");
+        assert_eq!(format_timestamp(4_102_444_799), "2099-12-31 23:59");
+    }
+
+    #[test]
+    fn command_safe_path_guards_option_like_names() {
+        assert_eq!(command_safe_path(Path::new("")), Path::new("."));
+        assert_eq!(command_safe_path(Path::new("-rf")), Path::new("./-rf"));
+        assert_eq!(command_safe_path(Path::new("!")), Path::new("./!"));
+        assert_eq!(command_safe_path(Path::new("(")), Path::new("./("));
+        assert_eq!(command_safe_path(Path::new("/abs/-x")), Path::new("/abs/-x"));
+        assert_eq!(command_safe_path(Path::new("dir/-x")), Path::new("dir/-x"));
+    }
+
+    #[test]
+    fn rsync_command_appends_to_partial_files_when_resuming() {
+        let mut download = Download::queued(
+            1,
+            "Show".to_string(),
+            "host".to_string(),
+            PathBuf::from("/srv/Show"),
+            "/tmp/out".to_string(),
+            EntryKind::Directory,
+        );
+        let args = |download: &Download| -> Vec<OsString> {
+            rsync_command(download, "/tmp/sock-%C").get_args().map(OsStr::to_os_string).collect()
+        };
+
+        let fresh = args(&download);
+        assert!(!fresh.iter().any(|a| a == "--append-verify"));
+
+        download.resume = true;
+        let resumed = args(&download);
+        assert_eq!(
+            resumed,
+            [
+                "-vrtzhP",
+                "--info=progress2",
+                "--protect-args",
+                "--append-verify",
+                "-e",
+                "ssh -o ControlMaster=auto -o ControlPath='/tmp/sock-%C' -o ControlPersist=600",
+                "--",
+                "host:/srv/Show",
+                "/tmp/out/",
+            ]
+        );
+    }
+
+    #[test]
+    fn rsync_command_copies_single_files_into_the_destination_folder() {
+        let mut download = Download::queued(
+            1,
+            "ep1.mkv".to_string(),
+            "host".to_string(),
+            PathBuf::from("/srv/Show/ep1.mkv"),
+            "./downloads".to_string(),
+            EntryKind::File,
+        );
+        let tail = |download: &Download| -> Vec<OsString> {
+            let args: Vec<OsString> = rsync_command(download, "/tmp/sock").get_args().map(OsStr::to_os_string).collect();
+            args[args.len() - 2..].to_vec()
+        };
+        assert_eq!(tail(&download), ["host:/srv/Show/ep1.mkv", "./downloads/"]);
+
+        download.local_dest = "/tmp/out/".to_string();
+        assert_eq!(tail(&download), ["host:/srv/Show/ep1.mkv", "/tmp/out/"]);
+    }
+
+    #[test]
+    fn cancel_download_at_drops_queued_and_stops_running() {
+        let download = |id, status| Download {
+            status,
+            ..Download::queued(
+                id,
+                format!("d{}", id),
+                "host".to_string(),
+                PathBuf::from("/srv"),
+                "/tmp/out".to_string(),
+                EntryKind::Directory,
+            )
+        };
+        let mut downloads = vec![
+            download(1, DownloadStatus::Queued),
+            download(2, DownloadStatus::Downloading),
+            download(3, DownloadStatus::Paused),
+            download(4, DownloadStatus::Completed),
+        ];
+        let child = Command::new("sleep").arg("10").spawn().unwrap();
+        let child = Arc::new(Mutex::new(child));
+        downloads[1].child = Some(Arc::clone(&child));
+
+        // Queued: gone from the list, nothing to record
+        assert_eq!(cancel_download_at(&mut downloads, 0).unwrap(), "Removed from queue: d1");
+        assert_eq!(downloads.iter().map(|d| d.id).collect::<Vec<_>>(), [2, 3, 4]);
+
+        // Running: cancelled and rsync stopped
+        assert_eq!(cancel_download_at(&mut downloads, 0).unwrap(), "Cancelled: d2");
+        assert_eq!(downloads[0].status, DownloadStatus::Cancelled);
+        assert!(downloads[0].completed_at.is_some() && downloads[0].child.is_none());
+        assert!(child.lock().unwrap().wait().unwrap().code().is_none());
+
+        // Paused: cancelled too
+        assert_eq!(cancel_download_at(&mut downloads, 1).unwrap(), "Cancelled: d3");
+        assert_eq!(downloads[1].status, DownloadStatus::Cancelled);
+
+        // Finished ones stay as they are
+        assert!(cancel_download_at(&mut downloads, 2).unwrap().starts_with("Only"));
+        assert_eq!(downloads[2].status, DownloadStatus::Completed);
+        assert!(cancel_download_at(&mut downloads, 9).is_none());
+    }
+
+    #[test]
+    fn record_finished_keeps_failed_downloads_for_retrying() {
+        let download = |id, status| Download {
+            status,
+            started_at: Some(1_700_000_000),
+            completed_at: Some(1_700_000_060),
+            ..Download::queued(
+                id,
+                format!("d{}", id),
+                "host".to_string(),
+                PathBuf::from("/srv"),
+                "/tmp/out".to_string(),
+                EntryKind::File,
+            )
+        };
+        let mut downloads = vec![
+            download(1, DownloadStatus::Queued),
+            download(2, DownloadStatus::Completed),
+            download(3, DownloadStatus::Failed("disk full".to_string())),
+            download(4, DownloadStatus::Downloading),
+            download(5, DownloadStatus::Cancelled),
+            download(6, DownloadStatus::Paused),
+        ];
+        downloads[1].total_size = Some(2048);
+
+        let recorded = record_finished(&mut downloads);
+        assert_eq!(downloads.iter().map(|d| d.id).collect::<Vec<_>>(), [1, 3, 4, 6]);
+        assert_eq!(
+            recorded.iter().map(|h| (h.folder_name.as_str(), h.outcome.clone())).collect::<Vec<_>>(),
+            [
+                ("d2", Outcome::Completed),
+                ("d3", Outcome::Failed("disk full".to_string())),
+                ("d5", Outcome::Cancelled),
+            ]
+        );
+        let entry = &recorded[0];
+        assert_eq!((entry.bytes, entry.duration, entry.downloaded_at), (Some(2048), 60, 1_700_000_060));
+        assert_eq!((entry.kind, entry.local_dest.as_str()), (EntryKind::File, "/tmp/out"));
+
+        // The failed one is only recorded once, and again after a retry fails
+        assert!(record_finished(&mut downloads).is_empty());
+        downloads[1].recorded = false;
+        assert_eq!(record_finished(&mut downloads).len(), 1);
+    }
+
+    #[test]
+    fn outcome_filter_cycles_and_matches() {
+        let mut filter = OutcomeFilter::All;
+        let mut labels = Vec::new();
+        for _ in 0..4 {
+            labels.push(filter.label());
+            filter = filter.next();
+        }
+        assert_eq!(labels, ["all", "completed", "failed", "cancelled"]);
+        assert!(filter == OutcomeFilter::All);
+
+        let outcomes = [Outcome::Completed, Outcome::Failed("x".to_string()), Outcome::Cancelled];
+        let matching = |filter: OutcomeFilter| outcomes.iter().map(|o| filter.matches(o)).collect::<Vec<_>>();
+        assert_eq!(matching(OutcomeFilter::All), [true, true, true]);
+        assert_eq!(matching(OutcomeFilter::Completed), [true, false, false]);
+        assert_eq!(matching(OutcomeFilter::Failed), [false, true, false]);
+        assert_eq!(matching(OutcomeFilter::Cancelled), [false, false, true]);
+    }
+
+    #[test]
+    fn terminate_sends_sigterm() {
+        use std::os::unix::process::ExitStatusExt;
+
+        // The trap only runs for a catchable signal, like rsync's partial file handler
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("trap 'kill $!; exit 3' TERM; sleep 10 & wait")
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        terminate(&mut child).unwrap();
+        let status = child.wait().unwrap();
+        assert_eq!(status.code(), Some(3), "{:?}", status.signal());
+
+        // Already exited: nothing to do
+        terminate(&mut child).unwrap();
+    }
+
+    #[test]
+    fn rsync_remote_arg_escapes_only_wildcards() {
+        let arg = |path: &str| rsync_remote_arg("host", Path::new(path));
+        assert_eq!(arg("a b/$c;d"), "host:a b/$c;d");
+        assert_eq!(arg("ep[1]*.mkv"), "host:ep\\[1\\]\\*.mkv");
+        assert_eq!(arg("why?\\"), "host:why\\?\\\\");
+        assert_eq!(arg(""), "host:.");
+        assert_eq!(arg("--delete"), "host:./--delete");
+
+        let raw = rsync_remote_arg("host", Path::new(OsStr::from_bytes(NON_UTF8_NAME)));
+        assert_eq!(raw.as_bytes(), [b"host:".as_slice(), NON_UTF8_NAME].concat());
+    }
+
+    #[test]
+    fn parse_args_reads_jobs() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+
+        let options = parse_args(&args(&["host:dir", "./out"])).unwrap();
+        assert_eq!(options.jobs, DEFAULT_JOBS);
+        assert_eq!(options.remote_source, "host:dir");
+        assert_eq!(options.local_dest, "./out");
+
+        assert_eq!(parse_args(&args(&["--jobs", "3", "host", "out"])).unwrap().jobs, 3);
+        assert_eq!(parse_args(&args(&["host", "-j", "4", "out"])).unwrap().jobs, 4);
+        assert_eq!(parse_args(&args(&["--jobs=2", "host", "out"])).unwrap().jobs, 2);
+
+        let options = parse_args(&args(&["--retries", "0", "--retry-delay=2", "host", "out"])).unwrap();
+        assert_eq!(options.retry_policy.max_retries, 0);
+        assert_eq!(options.retry_policy.base_delay, 2);
+
+        assert!(parse_args(&args(&["--jobs", "0", "host", "out"])).is_err());
+        assert!(parse_args(&args(&["--jobs", "host", "out"])).is_err());
+        assert!(parse_args(&args(&["--bogus", "host", "out"])).is_err());
+        assert!(parse_args(&args(&["host"])).is_err());
+
+        assert_eq!(parse_args(&args(&["--export-history", "csv"])).unwrap().export_history, Some(ExportFormat::Csv));
+        assert!(parse_args(&args(&["--export-history=xml"])).is_err());
+        assert_eq!(parse_args(&args(&["--search-depth", "3", "host", "out"])).unwrap().search_depth, 3);
+        assert!(!options.jail);
+        assert!(parse_args(&args(&["--jail", "host", "out"])).unwrap().jail);
+    }
+
+    #[test]
+    fn parse_rsync_line_reads_progress2() {
+        let mut current_file = String::new();
+        assert!(parse_rsync_line("season 1/ep10.mkv", &mut current_file).is_none());
+        assert_eq!(current_file, "ep10.mkv");
+
+        let progress = parse_rsync_line("        12.35M  45%    1.23MB/s    0:00:12 (xfr#1, to-chk=3/5)", &mut current_file)
+            .unwrap();
+        assert_eq!(progress.percentage, 45);
+        assert_eq!(progress.speed, "1.23MB/s");
+        assert_eq!(progress.bytes_transferred, 12_350_000);
+        assert_eq!(progress.eta, "0:00:12");
+        assert_eq!(progress.file_name, "ep10.mkv");
+
+        let progress = parse_rsync_line("  1,234,567 100%  800.00kB/s    0:00:01", &mut current_file).unwrap();
+        assert_eq!(progress.bytes_transferred, 1_234_567);
+        assert_eq!(progress.percentage, 100);
+    }
+
+    #[test]
+    fn output_lines_split_on_carriage_returns() {
+        let mut lines = Vec::new();
+        for_each_output_line(&b"file\n  1  10%\r  5  50%\r\n"[..], |line| lines.push(line.to_string()));
+        assert_eq!(lines, ["file", "  1  10%", "  5  50%"]);
+    }
+
+    #[test]
+    fn retry_delay_backs_off_exponentially() {
+        let policy = RetryPolicy { max_retries: 10, base_delay: 5 };
+        assert_eq!(policy.delay(1), 5);
+        assert_eq!(policy.delay(2), 10);
+        assert_eq!(policy.delay(4), 40);
+        assert_eq!(policy.delay(7), MAX_RETRY_DELAY);
+        assert_eq!(policy.delay(200), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn failure_reason_prefers_stderr_over_exit_code() {
+        let tail = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<VecDeque<_>>();
+
+        assert_eq!(failure_reason(12, &tail(&[])), "connection closed (exit code 12)");
+        assert_eq!(failure_reason(99, &tail(&[])), "rsync failed (exit code 99)");
+        assert_eq!(
+            failure_reason(11, &tail(&["rsync: write failed on \"x\": No space left on device (28)"])),
+            "disk full (exit code 11)"
+        );
+        assert_eq!(
+            failure_reason(23, &tail(&["rsync: opendir \"a\" failed: Permission denied (13)"])),
+            "permission denied (exit code 23)"
+        );
+    }
+
+    #[test]
+    fn clearing_shown_history_keeps_entries_hidden_by_filters() {
+        let entry = |name: &str, outcome| HistoryEntry {
+            folder_name: name.to_string(),
+            remote_host: "host".to_string(),
+            remote_path: Path::new("/data").join(name),
+            local_dest: "/dl".to_string(),
+            kind: EntryKind::Directory,
+            outcome,
+            bytes: None,
+            duration: 0,
+            downloaded_at: 1_700_000_000,
+        };
+        let mut history = vec![
+            entry("Show.S01", Outcome::Completed),
+            entry("Movie", Outcome::Completed),
+            entry("Show.S02", Outcome::Cancelled),
+            entry("Show.S03", Outcome::Completed),
+        ];
+
+        let shown = shown_history(&history, OutcomeFilter::Completed, "show");
+        assert_eq!(shown, [0, 3]);
+        remove_indices(&mut history, &shown);
+        let left: Vec<&str> = history.iter().map(|h| h.folder_name.as_str()).collect();
+        assert_eq!(left, ["Movie", "Show.S02"]);
+
+        assert_eq!(shown_history(&history, OutcomeFilter::All, ""), [0, 1]);
+    }
+
+    #[test]
+    fn history_lines_round_trip_hostile_names() {
+        let mut names: Vec<Vec<u8>> = HOSTILE_NAMES.iter().map(|n| n.as_bytes().to_vec()).collect();
+        names.push(NON_UTF8_NAME.to_vec());
+        names.push(b"\\x41 literal escape".to_vec());
+
+        for name in names {
+            let entry = HistoryEntry {
+                folder_name: display_lossy(&name),
+                remote_host: "user@host".to_string(),
+                remote_path: Path::new("/data").join(OsStr::from_bytes(&name)),
+                local_dest: String::from_utf8_lossy(&name).into_owned(),
+                kind: EntryKind::Symlink { to_dir: true },
+                outcome: Outcome::Failed(format!("{} (exit code 23)", display_lossy(&name))),
+                bytes: Some(1234),
+                duration: 61,
+                downloaded_at: 1_700_000_000,
+            };
+            let line = entry.to_line();
+            assert!(!line.contains('\n'), "line break in {:?}", line);
+            assert_eq!(HistoryEntry::from_line(&line), Some(entry));
+        }
+
+        assert_eq!(parse_total_size("total size is 1.50G  speedup is 1.00"), Some(1_500_000_000));
+        assert_eq!(HistoryEntry::from_line("garbage"), None);
+        assert_eq!(HistoryEntry::from_line("1700000000\t5\t\thost\t/data/x\t/dl\tx"), None);
+        assert_eq!(HistoryEntry::from_line("1700000000\t5\t\thost\t/data/x\t/dl\tx\td"), None);
+        assert_eq!(HistoryEntry::from_line("1700000000\t5\t\thost\t/data/x\t/dl\tx\tq\tcompleted"), None);
+        let line = HistoryEntry::from_line("1700000000\t5\t\thost\t/data/x\t/dl\tx\td\tcompleted").unwrap();
+        assert!(line.kind == EntryKind::Directory && line.bytes.is_none());
+        assert_eq!(line.outcome, Outcome::Completed);
+    }
+
+    #[test]
+    fn bookmark_lines_round_trip_hostile_names() {
+        let mut paths: Vec<PathBuf> = HOSTILE_NAMES.iter().map(|name| Path::new("/srv").join(name)).collect();
+        paths.push(Path::new("/srv").join(OsStr::from_bytes(NON_UTF8_NAME)));
+        for path in paths {
+            let bookmark = Bookmark {
+                host: "user@host\tx".to_string(),
+                path,
+            };
+            let line = bookmark.to_line();
+            assert!(!line.contains('\n'));
+            assert_eq!(Bookmark::from_line(&line), Some(bookmark));
+        }
+        assert_eq!(Bookmark::from_line("no tab"), None);
+        assert_eq!(Bookmark::from_line("\t/srv"), None);
+    }
+
+    #[test]
+    fn absolute_local_dest_starts_from_current_dir() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(absolute_local_dest("./downloads"), cwd.join("downloads").to_string_lossy());
+        assert_eq!(absolute_local_dest("out/tv"), cwd.join("out/tv").to_string_lossy());
+        assert_eq!(absolute_local_dest("/srv/media"), "/srv/media");
+        assert_eq!(absolute_local_dest(""), "");
+    }
+
+    #[test]
+    fn export_history_escapes_json_and_csv() {
+        let entry = HistoryEntry {
+            folder_name: "a,\"b\"".to_string(),
+            remote_host: "host".to_string(),
+            remote_path: PathBuf::from("/data/a,\"b\""),
+            local_dest: "./out".to_string(),
+            kind: EntryKind::Directory,
+            outcome: Outcome::Failed("disk full\n(exit code 11)".to_string()),
+            bytes: None,
+            duration: 90,
+            downloaded_at: 1_700_000_090,
+        };
+
+        let mut out = Vec::new();
+        export_history([&entry], ExportFormat::JsonLines, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"host\":\"host\",\"remote_path\":\"/data/a,\\\"b\\\"\",\"local_path\":\"./out\",\
+             \"name\":\"a,\\\"b\\\"\",\"started_at\":\"2023-11-14T22:13:20Z\",\
+             \"ended_at\":\"2023-11-14T22:14:50Z\",\"duration_secs\":90,\"bytes\":null,\
+             \"outcome\":\"failed\",\"error\":\"disk full\\n(exit code 11)\"}\n"
+        );
+
+        let mut out = Vec::new();
+        export_history([&entry], ExportFormat::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "host,remote_path,local_path,name,started_at,ended_at,duration_secs,bytes,outcome,error\n\
+             host,\"/data/a,\"\"b\"\"\",./out,\"a,\"\"b\"\"\",2023-11-14T22:13:20Z,2023-11-14T22:14:50Z,\
+             90,,failed,\"disk full\n(exit code 11)\"\n"
+        );
+    }
+
+    #[test]
+    fn fuzzy_matches_any_checks_name_and_path() {
+        let candidates = ["Season 1", "host:/srv/media/shows/season1"];
+        assert!(fuzzy_matches_any("", &candidates));
+        assert!(fuzzy_matches_any("sea1", &candidates));
+        assert!(fuzzy_matches_any("srvmedia", &candidates));
+        assert!(!fuzzy_matches_any("movies", &candidates));
+    }
+
+    #[test]
+    fn highlight_matches_groups_runs() {
+        let spans = highlight_matches("season1/", &[0, 1, 6]);
+        let texts: Vec<&str> = spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(texts, ["se", "ason", "1", "/"]);
+        assert_eq!(spans[0].style, spans[2].style);
+        assert_eq!(spans[1].style, Style::default());
+    }
+
+    #[test]
+    fn search_streams_relative_paths_down_to_depth() {
+        let dir = env::temp_dir().join(format!("lakach-test-search-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a/new\nline/deep")).unwrap();
+        std::fs::write(dir.join("a/new\nline/deep/too far"), b"x").unwrap();
+        std::fs::write(dir.join("top file"), b"12345").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(search_script(&dir, 3));
+        let result = run_search(&mut command, &Mutex::new(None), &sender);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok(()));
+
+        drop(sender);
+        let mut found: Vec<(OsString, EntryKind)> = receiver
+            .iter()
+            .flat_map(|event| match event {
+                SearchEvent::Found(batch) => batch,
+                SearchEvent::Finished(_) => Vec::new(),
+            })
+            .map(|f| (f.name, f.kind))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            [
+                (OsString::from("a"), EntryKind::Directory),
+                (OsString::from("a/new\nline"), EntryKind::Directory),
+                (OsString::from("a/new\nline/deep"), EntryKind::Directory),
+                (OsString::from("top file"), EntryKind::File),
+            ]
+        );
+    }
+
+    #[test]
+    fn run_killable_stops_when_killed() {
+        let slot: Arc<Mutex<Option<Child>>> = Arc::new(Mutex::new(None));
+        let killer = {
+            let slot = Arc::clone(&slot);
+            thread::spawn(move || {
+                // Wait for the child to be parked, then kill it
+                loop {
+                    if let Some(child) = slot.lock().unwrap().as_mut() {
+                        child.kill().unwrap();
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+            })
+        };
+
+        let started = std::time::Instant::now();
+        let output = run_killable(Command::new("sh").arg("-c").arg("echo partial; exec sleep 30"), &slot).unwrap();
+        killer.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!output.status.success());
+        assert!(slot.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn listing_cache_honours_ttl_sizes_and_connect() {
+        let folder = |name: &str| FolderInfo {
+            name: OsString::from(name),
+            display: name.to_string(),
+            kind: EntryKind::Directory,
+            size: None,
+            modified: 0,
+            owner: "u".to_string(),
+            mode: 0o755,
+        };
+        let names = |folders: Option<&[FolderInfo]>| folders.map(|f| f.iter().map(|f| f.display.clone()).collect::<Vec<_>>());
+        let reload = ListingPurpose::Reload;
+        let connect = ListingPurpose::Connect { host: "host".to_string() };
+        let key = ("host".to_string(), PathBuf::from("/srv"));
+        let start = Instant::now();
+
+        let mut cache = ListingCache::default();
+        assert!(cache.lookup(&key, &reload, false, start).is_none());
+        cache.insert(key.clone(), vec![folder("a")], false, start);
+        assert_eq!(names(cache.lookup(&key, &reload, false, start)), Some(vec!["a".to_string()]));
+        // Other hosts and folders have their own entries
+        assert!(cache.lookup(&("other".to_string(), key.1.clone()), &reload, false, start).is_none());
+        // A listing without sizes can't stand in for one with them, but not the other way round
+        assert!(cache.lookup(&key, &reload, true, start).is_none());
+        cache.insert(key.clone(), vec![folder("b")], true, start);
+        assert_eq!(names(cache.lookup(&key, &reload, false, start)), Some(vec!["b".to_string()]));
+        // Connecting always goes to the remote
+        assert!(cache.lookup(&key, &connect, false, start).is_none());
+
+        // Expires after the TTL, and expired entries are dropped on the next insert
+        let later = start + LISTING_CACHE_TTL;
+        assert!(cache.lookup(&key, &reload, false, later - Duration::from_secs(1)).is_some());
+        assert!(cache.lookup(&key, &reload, false, later).is_none());
+        cache.insert(("host".to_string(), PathBuf::from("/tmp")), Vec::new(), false, later);
+        assert_eq!(cache.entries.len(), 1);
+
+        // Refreshing forgets the entry
+        let tmp = ("host".to_string(), PathBuf::from("/tmp"));
+        cache.invalidate(&tmp);
+        assert!(cache.lookup(&tmp, &reload, false, later).is_none());
+    }
+
+    #[test]
+    fn nav_history_steps_back_and_forward() {
+        let place = |path: &str, selected: usize| NavEntry {
+            host: "host".to_string(),
+            home: PathBuf::from("/home/u"),
+            base: PathBuf::from("/"),
+            path: PathBuf::from(path),
+            selected: Some(selected),
+            filter: String::new(),
+        };
+        let mut history = NavHistory::default();
+        assert!(history.peek(true).is_none());
+        history.record(place("/a", 1));
+        history.record(place("/b", 2));
+
+        // At /c: back to /b, back to /a, then forward to /b again
+        assert_eq!(history.step(true, place("/c", 3)), Some(place("/b", 2)));
+        assert_eq!(history.step(true, place("/b", 4)), Some(place("/a", 1)));
+        assert!(history.step(true, place("/a", 0)).is_none());
+        assert_eq!(history.peek(false), Some(&place("/b", 4)));
+        assert_eq!(history.step(false, place("/a", 5)), Some(place("/b", 4)));
+        assert_eq!(history.peek(true), Some(&place("/a", 5)));
+
+        // Going somewhere new drops the way forward
+        history.record(place("/b", 6));
+        assert!(history.peek(false).is_none());
+
+        for i in 0..MAX_NAV_HISTORY + 10 {
+            history.record(place("/x", i));
+        }
+        assert_eq!(history.back.len(), MAX_NAV_HISTORY);
+        assert_eq!(history.peek(true), Some(&place("/x", MAX_NAV_HISTORY + 9)));
+    }
+
+    #[test]
+    fn glob_match_handles_wildcards_and_classes() {
+        assert!(glob_match("*S01*", "Show.S01E02.mkv"));
+        assert!(!glob_match("*S01*", "Show.S02E01.mkv"));
+        assert!(glob_match("ep?", "ep1"));
+        assert!(!glob_match("ep?", "ep10"));
+        assert!(glob_match("ep[0-9][0-9]", "ep42"));
+        assert!(!glob_match("ep[!0-9]", "ep4"));
+        assert!(glob_match("ep[^0-9]", "epx"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**a*b", "xxaybz b"));
+        assert!(!glob_match("a*b", "a"));
+        assert!(glob_match("café*", "café au lait"));
+    }
+
+    #[test]
+    fn resolve_remote_path_handles_relative_home_and_dots() {
+        let resolve_remote_path = |base, input| resolve_remote_path(base, Path::new(""), input);
+        let base = Path::new("media/tv");
+        assert_eq!(resolve_remote_path(base, "Show/S01"), Path::new("media/tv/Show/S01"));
+        assert_eq!(resolve_remote_path(base, "../films/"), Path::new("media/films"));
+        assert_eq!(resolve_remote_path(base, "./a//b/."), Path::new("media/tv/a/b"));
+        assert_eq!(resolve_remote_path(base, "/srv/../etc"), Path::new("/etc"));
+        assert_eq!(resolve_remote_path(base, "/.."), Path::new("/"));
+        assert_eq!(resolve_remote_path(base, "~"), Path::new(""));
+        assert_eq!(resolve_remote_path(base, "~/x"), Path::new("x"));
+        assert_eq!(resolve_remote_path(base, "../../../up"), Path::new("../up"));
+        assert_eq!(resolve_remote_path(Path::new(""), "../.."), Path::new("../.."));
+        assert_eq!(super::resolve_remote_path(base, Path::new("/home/u"), "~/../x"), Path::new("/home/x"));
+    }
+
+    #[test]
+    fn common_prefix_stops_on_character_boundary() {
+        assert_eq!(common_prefix("Season 1", "Season 2"), "Season ");
+        assert_eq!(common_prefix("abc", "ab"), "ab");
+        assert_eq!(common_prefix("café", "cafè"), "caf");
+        assert_eq!(common_prefix("x", "y"), "");
+    }
+}